@@ -0,0 +1,41 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// stamps the git revision and build date into the binary for GET /version
+fn main() {
+    let git_sha = run("git", &["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=BARN_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=BARN_BUILD_DATE={}", build_date());
+    // a commit moves the branch ref, a checkout moves HEAD, and gc packs the refs
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=.git/packed-refs");
+}
+
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok().map(|s| s.trim().to_string())
+}
+
+// UTC in RFC 3339, computed here rather than by `date` so the build works on Windows too
+fn build_date() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (seconds / 86_400, seconds % 86_400);
+
+    // civil-from-days (Howard Hinnant), shifted so years start in March
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rest / 3_600, rest % 3_600 / 60, rest % 60)
+}
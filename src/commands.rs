@@ -0,0 +1,1684 @@
+use argon2::Argon2;
+use clap::{ArgMatches, CommandFactory, ValueSource};
+use barn::access_control::{self, AccessControl, Permission};
+use barn::audit;
+use barn::kv_silo::{decrypt_data, encrypt_data, get_or_create_key, load_key, reconstruct_dek, split_dek, write_atomically, Algorithm, KVStore, MergePolicy, PersistedSecrets, ShareConfig, ShareSerialization, SiloManager};
+use barn::tokens::TokenStore;
+use barn::users::{load_bcrypt_cost, register_user, UserStore};
+use barn::vault::{self, KvVersion};
+use dialoguer::FuzzySelect;
+use handlebars::Handlebars;
+use ::notify::event::{AccessKind, AccessMode, ModifyKind};
+use ::notify::{EventKind, RecursiveMode, Watcher};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sharks::Share;
+use ring::digest::{digest, SHA256};
+use sodiumoxide::{base64, hex};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use zeroize::Zeroizing;
+#[cfg(feature = "kms")]
+use aws_sdk_kms::{error::DisplayErrorContext, primitives::Blob};
+
+use crate::notify::{self, NotifierConfig};
+use crate::{running_pid, CertAlgorithm, Cli, Encoding, SealConfig, ServeArgs, ACCESS_FILE, AUDIT_FILE, AUDIT_OLD_FILE, BCRYPT_COST_FILE, CHECKPOINTS_DIR, KEY_FILE, SEALED_SECRETS_FILE, SEAL_FILE, SECRETS_FILE, SHARES_DIR, TOKENS_FILE, USERS_FILE};
+
+const SALT_LEN: usize = 16;
+// scratch file kv_round_trip writes under the data directory and removes again
+const SELF_TEST_FILE: &str = "self_test.bin";
+const NONCE_LEN: usize = 24;
+const PEM_LABEL: &str = "BARN SECRET";
+const BENCHMARK_PAYLOAD: usize = 1024 * 1024;
+const BENCHMARK_DURATION: Duration = Duration::from_secs(3);
+
+pub fn gen_key(output: Option<&str>, passphrase: bool, bits: usize) -> io::Result<()> {
+    let mut key = Zeroizing::new(vec![0u8; bits / 8]);
+
+    if passphrase {
+        let phrase = rpassword::prompt_password("Passphrase: ")?;
+        if phrase != rpassword::prompt_password("Confirm passphrase: ")? {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Passphrases do not match"));
+        }
+
+        // the salt is needed to derive the same key again, so show it alongside the key
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Argon2::default()
+            .hash_password_into(phrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        eprintln!("Salt: {}", hex::encode(salt));
+    } else {
+        OsRng.fill_bytes(&mut key);
+    }
+
+    match output {
+        Some(path) => {
+            write_key_file(path, &key)?;
+            eprintln!("Wrote {}-bit key to {}", bits, path);
+        }
+        None => println!("{}", hex::encode(&key)),
+    }
+
+    eprintln!("Fingerprint: {}", fingerprint(&key));
+    Ok(())
+}
+
+// round-trips 1 MB through each cipher for a few seconds and reports throughput
+pub fn cipher_benchmark() {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    let mut payload = vec![0u8; BENCHMARK_PAYLOAD];
+    OsRng.fill_bytes(&mut payload);
+
+    let mut fastest: Option<(&str, f64)> = None;
+    for algorithm in Algorithm::ALL {
+        let cipher = algorithm.cipher();
+        let name = cipher.name();
+        let start = Instant::now();
+        let mut rounds = 0u32;
+        while start.elapsed() < BENCHMARK_DURATION {
+            let (iv, ciphertext) = cipher.encrypt(&key, &payload).expect("benchmark key is 32 bytes");
+            cipher.decrypt(&key, &iv, &ciphertext).expect("benchmark ciphertext is untouched");
+            rounds += 1;
+        }
+
+        let throughput = rounds as f64 / start.elapsed().as_secs_f64();
+        println!("{}: {:.1} MB/s", name, throughput);
+        if fastest.is_none_or(|(_, best)| throughput > best) {
+            fastest = Some((name, throughput));
+        }
+    }
+
+    if let Some((name, throughput)) = fastest {
+        println!("Recommended cipher: {} ({:.1} MB/s)", name, throughput);
+    }
+}
+
+// first 8 bytes of the SHA-256 of the key, enough to tell keys apart without revealing them
+pub fn fingerprint(key: &[u8]) -> String {
+    hex::encode(&digest(&SHA256, key).as_ref()[..8])
+}
+
+// owner-only even when path already exists, since mode only applies to a newly created file;
+// the permissions are narrowed before anything is written
+fn write_key_file(path: &str, key: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(key)
+}
+
+// encodes the stored nonce + ciphertext as-is, the value is never decrypted
+pub fn encode(key: &str, format: Encoding) -> io::Result<()> {
+    let data_dir = "data";
+    let blob = fs::read(format!("{}/{}.dat", data_dir, key))?;
+
+    let encoded = match format {
+        Encoding::Base64 => base64::encode(&blob, base64::Variant::Original),
+        Encoding::Base64Url => base64::encode(&blob, base64::Variant::UrlSafe),
+        Encoding::Hex => hex::encode(&blob),
+        Encoding::Pem => {
+            let body = base64::encode(&blob, base64::Variant::Original);
+            let lines: Vec<&str> = body
+                .as_bytes()
+                .chunks(64)
+                .map(|line| std::str::from_utf8(line).unwrap())
+                .collect();
+            format!("-----BEGIN {}-----\n{}\n-----END {}-----", PEM_LABEL, lines.join("\n"), PEM_LABEL)
+        }
+    };
+
+    println!("{}", encoded);
+    Ok(())
+}
+
+pub fn decode(key: &str, format: Encoding) -> io::Result<()> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    let input = input.trim();
+
+    let decoded = match format {
+        Encoding::Base64 => base64::decode(input, base64::Variant::Original),
+        Encoding::Base64Url => base64::decode(input, base64::Variant::UrlSafe),
+        Encoding::Hex => hex::decode(input),
+        Encoding::Pem => {
+            let body: String = input.lines().filter(|line| !line.starts_with("-----")).collect();
+            base64::decode(body, base64::Variant::Original)
+        }
+    };
+
+    let blob = match decoded {
+        Ok(blob) if blob.len() > NONCE_LEN => blob,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Input is not a valid encoded ciphertext")),
+    };
+
+    let data_dir = "data";
+    fs::create_dir_all(data_dir)?;
+    write_atomically(format!("{}/{}.dat", data_dir, key), &blob)?;
+    eprintln!("Stored ciphertext for {}", key);
+    Ok(())
+}
+
+pub async fn verify_key(key_file: &str, store_file: &str) -> io::Result<()> {
+    let key = Zeroizing::new(fs::read(key_file)?);
+    if key.len() != 32 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Key file must contain a 32-byte key"));
+    }
+
+    let persisted = match KVStore::new().decrypt_file(store_file, &key).await {
+        Ok(plaintext) => serde_json::from_slice::<PersistedSecrets>(&plaintext).ok(),
+        // a failed decryption surfaces as InvalidData, anything else means the file could not be read
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => None,
+        Err(e) => return Err(e),
+    };
+
+    match persisted {
+        Some(persisted) => {
+            println!("Key verified: {} secrets readable", persisted.secrets.len());
+            Ok(())
+        }
+        None => {
+            println!("Key mismatch: decryption failed");
+            std::process::exit(1);
+        }
+    }
+}
+
+// output is "{nonce_hex}\n{ciphertext_hex}\n", so binary input passes through text-only pipes
+pub fn encrypt_stdin() -> io::Result<()> {
+    let master_key = get_or_create_key(KEY_FILE)?;
+    let mut plaintext = Zeroizing::new(Vec::new());
+    io::stdin().read_to_end(&mut plaintext)?;
+
+    let (nonce, ciphertext) = encrypt_data(&master_key, &plaintext)?;
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", hex::encode(nonce))?;
+    writeln!(stdout, "{}", hex::encode(ciphertext))
+}
+
+// the inverse of encrypt_stdin; the plaintext is written out as raw bytes
+pub fn decrypt_stdin() -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+
+    let mut lines = input.lines().map(str::trim).filter(|line| !line.is_empty());
+    let parts = (lines.next(), lines.next(), lines.next());
+    let (nonce, ciphertext) = match parts {
+        (Some(nonce), Some(ciphertext), None) => (hex::decode(nonce), hex::decode(ciphertext)),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Input must be the nonce and the ciphertext as two hex lines")),
+    };
+    let (nonce, ciphertext) = match (nonce, ciphertext) {
+        (Ok(nonce), Ok(ciphertext)) => (nonce, ciphertext),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Input is not valid hex")),
+    };
+
+    let plaintext = decrypt_data(&master_key, &nonce, &ciphertext)?;
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&plaintext)?;
+    stdout.flush()
+}
+
+// reads the key without get_or_create_key so a missing key is reported instead of generated
+pub fn test_encryption(plaintext: &str) -> io::Result<()> {
+    let key = load_key(KEY_FILE)?;
+
+    let (nonce, ciphertext) = encrypt_data(&key, plaintext.as_bytes())?;
+    println!("Nonce: {}", hex::encode(&nonce));
+    println!("Ciphertext: {} bytes", ciphertext.len());
+
+    match decrypt_data(&key, &nonce, &ciphertext) {
+        Ok(decrypted) if *decrypted == plaintext.as_bytes() => println!("OK ({} bytes)", decrypted.len()),
+        Ok(decrypted) => {
+            let first_diff = decrypted
+                .iter()
+                .zip(plaintext.as_bytes())
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| decrypted.len().min(plaintext.len()));
+            println!(
+                "FAIL: decrypted {} bytes, expected {}, first difference at byte {}",
+                decrypted.len(),
+                plaintext.len(),
+                first_diff
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("FAIL: {}", e);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+// a random probe goes through a scratch store, is written to data_dir and read back; the real
+// secrets file is never touched, so this can run against a live server's directory
+pub async fn kv_round_trip(data_dir: &str, master_key: &[u8], algorithm: Algorithm) -> io::Result<()> {
+    let mut probe = [0u8; 32];
+    OsRng.fill_bytes(&mut probe);
+    let path = std::path::Path::new(data_dir).join(SELF_TEST_FILE);
+    let path = path.to_str().unwrap_or_default();
+
+    let result = async {
+        let (iv, encrypted_value) = encrypt_data(master_key, &probe)?;
+        let store = KVStore::with_data_dir(data_dir).with_algorithm(algorithm);
+        store.set_secret("self-test".to_string(), iv, encrypted_value).await?;
+        store.persist(path, master_key).await?;
+
+        let restored = KVStore::with_data_dir(data_dir);
+        restored.restore(path, master_key).await?;
+        let secret = restored.get_secret("self-test").await.ok_or_else(|| io::Error::other("probe missing after reload"))?;
+        if decrypt_data(master_key, &secret.iv, &secret.encrypted_value)?[..] != probe {
+            return Err(io::Error::other("probe changed on the round trip"));
+        }
+        Ok(())
+    }
+    .await;
+
+    let _ = fs::remove_file(path);
+    result
+}
+
+enum Check {
+    Pass(String),
+    Skip(String),
+    Fail(String),
+}
+
+// read-only apart from a scratch store in the temp directory; the key file is never created
+pub async fn self_test() -> io::Result<()> {
+    let seal: Option<SealConfig> = fs::read(SEAL_FILE).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok());
+    let mut checks = Vec::new();
+
+    let key = match fs::read(KEY_FILE).map(Zeroizing::new) {
+        Ok(key) if key.len() == 32 => {
+            checks.push(("key file", Check::Pass(format!("{} holds a 32-byte key, fingerprint {}", KEY_FILE, fingerprint(&key)))));
+            Some(key)
+        }
+        Ok(key) => {
+            checks.push(("key file", Check::Fail(format!("{} holds {} bytes, expected 32", KEY_FILE, key.len()))));
+            None
+        }
+        Err(_) if seal.is_some() => {
+            checks.push(("key file", Check::Skip("sealed, the key only exists as shares".to_string())));
+            None
+        }
+        Err(e) => {
+            checks.push(("key file", Check::Fail(format!("cannot read {}: {}", KEY_FILE, e))));
+            None
+        }
+    };
+
+    // a sealed store has no key file, so the rest runs with the key the shares rebuild
+    let (shares, rebuilt) = check_shares(seal.as_ref());
+    checks.push(("key shares", shares));
+    let key = key.or(rebuilt);
+
+    let (encryption, store) = match &key {
+        Some(key) => {
+            let mut probe = [0u8; 64];
+            OsRng.fill_bytes(&mut probe);
+            let encryption = match encrypt_data(key, &probe).and_then(|(nonce, ciphertext)| decrypt_data(key, &nonce, &ciphertext)) {
+                Ok(decrypted) if decrypted[..] == probe => Check::Pass("encrypt and decrypt agree".to_string()),
+                Ok(_) => Check::Fail("decrypted bytes differ from the plaintext".to_string()),
+                Err(e) => Check::Fail(e.to_string()),
+            };
+
+            let dir = std::env::temp_dir().join(format!("barn-self-test-{}", std::process::id()));
+            fs::create_dir_all(&dir)?;
+            let round_trip = kv_round_trip(dir.to_str().unwrap_or_default(), key, Algorithm::default()).await;
+            let _ = fs::remove_dir_all(&dir);
+            let store = match round_trip {
+                Ok(()) => Check::Pass("saved and reloaded a store in the temp directory".to_string()),
+                Err(e) => Check::Fail(e.to_string()),
+            };
+            (encryption, store)
+        }
+        None => (Check::Skip("no usable key".to_string()), Check::Skip("no usable key".to_string())),
+    };
+    checks.push(("encryption round trip", encryption));
+    checks.push(("store save and load", store));
+
+    let secrets = match &key {
+        Some(_) if !std::path::Path::new(SECRETS_FILE).exists() => Check::Skip(format!("{} does not exist yet", SECRETS_FILE)),
+        Some(key) => match KVStore::new().decrypt_file(SECRETS_FILE, key).await {
+            Ok(plaintext) => match serde_json::from_slice::<PersistedSecrets>(&plaintext) {
+                Ok(persisted) => Check::Pass(format!("{} secrets readable", persisted.secrets.len())),
+                Err(e) => Check::Fail(format!("{} decrypts but does not parse: {}", SECRETS_FILE, e)),
+            },
+            Err(e) => Check::Fail(format!("{}: {}", SECRETS_FILE, e)),
+        },
+        None => Check::Skip("no usable key".to_string()),
+    };
+    checks.push(("secrets file", secrets));
+
+    // nothing in barn talks to another service or reads a config file at runtime
+    checks.push(("external backends", Check::Skip("none configured".to_string())));
+    checks.push(("configuration", Check::Skip("barn reads no config file, see print-config".to_string())));
+
+    let mut failed = 0;
+    for (name, check) in &checks {
+        match check {
+            Check::Pass(detail) => println!("PASS  {}: {}", name, detail),
+            Check::Skip(detail) => println!("SKIP  {}: {}", name, detail),
+            Check::Fail(detail) => {
+                failed += 1;
+                println!("FAIL  {}: {}", name, detail);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} of {} checks failed", failed, checks.len())));
+    }
+    Ok(())
+}
+
+// the share files in SHARES_DIR must rebuild the sealed key, which is returned when they do
+fn check_shares(seal: Option<&SealConfig>) -> (Check, Option<Zeroizing<Vec<u8>>>) {
+    let seal = match seal {
+        Some(seal) => seal,
+        None => return (Check::Skip("the store is not sealed".to_string()), None),
+    };
+    let shares: Vec<Share> = match read_share_files(std::path::Path::new(SHARES_DIR)) {
+        Ok(files) if !files.is_empty() => files.into_iter().map(|file| file.share).collect(),
+        Ok(_) => return (Check::Skip(format!("no share files in {}", SHARES_DIR)), None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return (Check::Skip(format!("no share files in {}", SHARES_DIR)), None),
+        Err(e) => return (Check::Fail(e.to_string()), None),
+    };
+    if shares.len() < seal.shares.threshold as usize {
+        return (Check::Fail(format!("{} share files, {} are needed to unseal", shares.len(), seal.shares.threshold)), None);
+    }
+
+    match reconstruct_dek(&shares, seal.shares.threshold) {
+        Ok(key) if fingerprint(&key) == seal.fingerprint => (Check::Pass(format!("{} shares rebuild the sealed key", shares.len())), Some(key)),
+        Ok(_) => (Check::Fail("the shares rebuild a different key than the one sealed".to_string()), None),
+        Err(e) => (Check::Fail(e.to_string()), None),
+    }
+}
+
+// the sealed key rebuilt from the share files in dir, for --reload-shares-on-access
+pub fn key_from_share_files(dir: &std::path::Path, seal: &SealConfig) -> io::Result<Zeroizing<Vec<u8>>> {
+    let shares: Vec<Share> = read_share_files(dir)?.into_iter().map(|file| file.share).collect();
+    match reconstruct_dek(&shares, seal.shares.threshold) {
+        Ok(key) if fingerprint(&key) == seal.fingerprint => Ok(key),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("the shares in {} rebuild a different key than the one sealed", dir.display()))),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", dir.display(), e))),
+    }
+}
+
+// the first sealed start generates the key, prints its shares once (or saves them to shares_dir
+// with save_shares) and keeps only the share config; later starts just read that config back
+pub fn load_or_init_seal(path: &str, threshold: u8, total: u8, save_shares: bool, shares_dir: &std::path::Path) -> io::Result<SealConfig> {
+    match fs::read(path) {
+        Ok(bytes) => return Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    let shares = ShareConfig::new(threshold, total).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    let mut key = Zeroizing::new(vec![0u8; 32]);
+    OsRng.fill_bytes(&mut key);
+    let split = split_dek(&key, shares.threshold, shares.total).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    let config = SealConfig { shares, fingerprint: fingerprint(&key) };
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_vec_pretty(&config)?)?;
+
+    println!("Generated a new master key split into {} shares, {} needed to unseal.", total, threshold);
+    if save_shares {
+        for path in write_share_files(shares_dir, &split)? {
+            println!("  Saved {}", path.display());
+        }
+        println!("Deliver them with `barn export-shares` and delete them, together they are the key.");
+        return Ok(config);
+    }
+    println!("Store these now, they are not saved anywhere:");
+    for (i, share) in split.iter().enumerate() {
+        println!("  Share {}: {}", i + 1, base64::encode(share.to_bytes(), base64::Variant::Original));
+    }
+    Ok(config)
+}
+
+// one share per file as base64, the form /unseal takes, named after the share's x value;
+// readable by the owner only, like the key file
+fn write_share_files(dir: &std::path::Path, shares: &[Share]) -> io::Result<Vec<std::path::PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for share in shares {
+        let bytes = Zeroizing::new(share.to_bytes());
+        let path = dir.join(format!("share-{}.txt", bytes[0]));
+        let text = Zeroizing::new(format!("{}\n", base64::encode(&bytes[..], base64::Variant::Original)));
+        write_key_file(path.to_str().unwrap_or_default(), text.as_bytes())?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+pub struct ShareFile {
+    pub path: std::path::PathBuf,
+    pub share: Share,
+    pub size_bytes: u64,
+    pub modified: SystemTime,
+}
+
+// every file in dir as written by write_share_files, sorted by name; a file that isn't a share
+// fails the whole read, so no command acts on a partial set
+pub fn read_share_files(dir: &std::path::Path) -> io::Result<Vec<ShareFile>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    let mut files = Vec::new();
+    for path in paths {
+        let metadata = fs::metadata(&path)?;
+        let text = Zeroizing::new(fs::read_to_string(&path)?);
+        // a share of the 32-byte master key is its x value followed by 32 y bytes
+        let share = base64::decode(text.trim(), base64::Variant::Original)
+            .ok()
+            .filter(|bytes| bytes.len() == 33)
+            .and_then(|bytes| Share::from_bytes(&bytes).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a key share", path.display())))?;
+        files.push(ShareFile { path, share, size_bytes: metadata.len(), modified: metadata.modified()? });
+    }
+    Ok(files)
+}
+
+// seal.json is read before anything is printed; without it the table is shown but the threshold,
+// and so the quorum, is unknown
+pub fn list_shares(data_dir: &str) -> io::Result<()> {
+    let shares_dir = std::path::Path::new(data_dir).join("shares");
+    let files = read_share_files(&shares_dir)?;
+    let seal_path = std::path::Path::new(data_dir).join("seal.json");
+    let seal: Option<SealConfig> = match fs::read(&seal_path) {
+        Ok(bytes) => Some(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    println!("{:<6}  {:<7}  {:<10}  FILE_MODIFIED_AT", "INDEX", "X_VALUE", "SIZE_BYTES");
+    for (i, file) in files.iter().enumerate() {
+        let modified = file.modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let x = file.share.to_bytes()[0];
+        println!("{:<6}  {:<7}  {:<10}  {}", i + 1, x, file.size_bytes, format_timestamp(modified));
+    }
+
+    let seal = match seal {
+        Some(seal) => seal,
+        None => {
+            println!("Quorum unknown: {} share files, and no {} to read the threshold from", files.len(), seal_path.display());
+            return Ok(());
+        }
+    };
+    let choice = if io::stdout().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never };
+    let mut stdout = StandardStream::stdout(choice);
+    if files.len() >= seal.shares.threshold as usize {
+        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+        writeln!(stdout, "Quorum achievable: {} share files, {} needed", files.len(), seal.shares.threshold)?;
+    } else {
+        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+        writeln!(stdout, "Quorum not achievable: {} share files, {} needed", files.len(), seal.shares.threshold)?;
+    }
+    stdout.reset()
+}
+
+pub fn fuzzy_find(multi: bool) -> io::Result<()> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "fuzzy-find needs an interactive terminal"));
+    }
+
+    let data_dir = "data";
+    let mut keys: Vec<String> = fs::read_dir(data_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_suffix(".dat").map(str::to_owned)
+        })
+        .collect();
+    keys.sort();
+
+    // picked keys drop out of the list, Esc ends the selection
+    while !keys.is_empty() {
+        let picked = FuzzySelect::new()
+            .with_prompt(if multi { "Select a key (Esc when done)" } else { "Select a key" })
+            .items(&keys)
+            .interact_opt()
+            .map_err(io::Error::other)?;
+
+        match picked {
+            Some(index) => println!("{}", keys.remove(index)),
+            None => break,
+        }
+
+        if !multi {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn register(username: &str, password: Option<String>) -> io::Result<()> {
+    let password = match password {
+        Some(password) => password,
+        None => rpassword::prompt_password("Password: ")?,
+    };
+
+    let master_key = get_or_create_key(KEY_FILE)?;
+    let users = UserStore::load(USERS_FILE, &master_key).await?;
+    register_user(&users, username, &password, load_bcrypt_cost(BCRYPT_COST_FILE)?, &master_key).await?;
+    println!("User {} registered successfully", username);
+    Ok(())
+}
+
+// each trimmed value is written back through the store and saved with persist, so the old value
+// becomes a version and disabled, expiry, description and tags carry over as on any update
+pub async fn trim(dry_run: bool, keys: Option<Vec<String>>) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+
+    let names: Vec<String> = match keys {
+        Some(mut keys) => {
+            keys.sort();
+            keys
+        }
+        None => store.list_keys().await,
+    };
+
+    let mut trimmed = 0;
+    for name in &names {
+        // version 0 is the current value, which a disabled secret still has
+        let secret = match store.get_secret_version(name, 0).await {
+            Some(secret) => secret,
+            None => {
+                eprintln!("{}: not found", name);
+                continue;
+            }
+        };
+
+        let value = decrypt_data(&master_key, &secret.iv, &secret.encrypted_value)?;
+        let text = match std::str::from_utf8(&value) {
+            Ok(text) => text,
+            Err(_) => {
+                eprintln!("{}: skipped, value is not UTF-8", name);
+                continue;
+            }
+        };
+
+        let trimmed_text = text.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        if trimmed_text.len() == text.len() {
+            continue;
+        }
+
+        if dry_run {
+            println!("Would trim {} ({} bytes removed)", name, text.len() - trimmed_text.len());
+        } else {
+            // written like any other update: the untrimmed value becomes a version and updated_at moves
+            let (iv, encrypted_value) = encrypt_data(&master_key, trimmed_text.as_bytes())?;
+            store.set_secret_expiring(name.clone(), iv, encrypted_value, secret.expires_at).await?;
+            println!("Trimmed {} ({} bytes removed)", name, text.len() - trimmed_text.len());
+        }
+        trimmed += 1;
+    }
+
+    // persist keeps the cipher restore read from the file header
+    if !dry_run && trimmed > 0 {
+        store.persist(SECRETS_FILE, &master_key).await?;
+    }
+    println!("{} of {} secrets {}", trimmed, names.len(), if dry_run { "would be trimmed" } else { "trimmed" });
+    Ok(())
+}
+
+pub fn audit_verify(file: &str) -> io::Result<()> {
+    match audit::verify(file)? {
+        Ok(entries) => {
+            println!("Audit log intact: {} entries", entries);
+            Ok(())
+        }
+        Err(broken) => {
+            println!("Audit log broken at line {}: {}", broken.line, broken.reason);
+            std::process::exit(1);
+        }
+    }
+}
+
+// prints each change, then the grants they leave in place; grants made before the log was
+// attached, or by `barn clone`, are not in it
+pub fn audit_grants(file: &str) -> io::Result<()> {
+    let changes = access_control::grant_history(file)?;
+    let level = |permission: Permission| format!("{:?}", permission).to_lowercase();
+    let mut current: BTreeMap<(String, String), Permission> = BTreeMap::new();
+    for change in &changes {
+        let by = change.by.as_deref().unwrap_or("unknown");
+        let key = (change.user_id.clone(), change.path.clone());
+        match change.permission {
+            Some(permission) => {
+                println!("{}  {} granted {} on {} to {}", format_timestamp(change.at), by, level(permission), change.path, change.user_id);
+                current.insert(key, permission);
+            }
+            None => {
+                println!("{}  {} revoked {} from {}", format_timestamp(change.at), by, change.path, change.user_id);
+                current.remove(&key);
+            }
+        }
+    }
+
+    println!("{} grant changes; grants in place afterwards:", changes.len());
+    for ((user_id, path), permission) in &current {
+        println!("  {}  {}  {}", user_id, path, level(*permission));
+    }
+    Ok(())
+}
+
+// the new key is kept beside the old one until every data file is re-encrypted,
+// so an interrupted rotation never leaves a file without the key that reads it
+pub async fn rotate_key(pid_file: Option<&str>, force: bool) -> io::Result<()> {
+    // a running server keeps persisting under the old key it holds in memory
+    if let Some(pid) = pid_file.and_then(running_pid) {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("the server is still running (PID {}), stop it before rotating the key", pid),
+        ));
+    }
+
+    // a run that stopped partway leaves files encrypted under the key in .new, which is
+    // their only copy; generating another would overwrite it
+    let pending_key_file = format!("{}.new", KEY_FILE);
+    if std::path::Path::new(&pending_key_file).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} is left from a rotation that did not finish; some files may only be readable with it, so keep it and restore data/ from a backup before rotating again",
+                pending_key_file
+            ),
+        ));
+    }
+
+    let old_key = Zeroizing::new(fs::read(KEY_FILE)?);
+
+    // values written by POST /store are nonce + ciphertext under the same key; any that can't be
+    // read now would be lost for good once the key is replaced, so they stop the run up front
+    let mut values = Vec::new();
+    let mut unreadable = Vec::new();
+    for entry in fs::read_dir("data")? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "dat") {
+            continue;
+        }
+
+        let blob = fs::read(&path)?;
+        let plaintext = if blob.len() > NONCE_LEN {
+            decrypt_data(&old_key, &blob[..NONCE_LEN], &blob[NONCE_LEN..]).ok()
+        } else {
+            None
+        };
+        match plaintext {
+            Some(plaintext) => values.push((path, plaintext)),
+            None => unreadable.push(path),
+        }
+    }
+    if !unreadable.is_empty() {
+        let names: Vec<_> = unreadable.iter().map(|path| path.display().to_string()).collect();
+        if !force {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not readable with the current key: {}; pass --force to rotate anyway", names.join(", ")),
+            ));
+        }
+        eprintln!("Warning: leaving {} unreadable, they can't be recovered after the rotation", names.join(", "));
+    }
+
+    let mut new_key = Zeroizing::new(vec![0u8; 32]);
+    OsRng.fill_bytes(&mut new_key);
+    write_key_file(&pending_key_file, &new_key)?;
+
+    if std::path::Path::new(SECRETS_FILE).exists() {
+        let store = KVStore::new();
+        store.rotate_master_key(&old_key, &new_key, SECRETS_FILE).await?;
+        println!("Re-encrypted {} secrets", store.list_keys().await.len());
+    }
+
+    let silos = SiloManager::new("data");
+    for name in silos.list_silos().await? {
+        let path = silos.silo_file(&name)?;
+        KVStore::new().rotate_master_key(&old_key, &new_key, path.to_str().unwrap_or_default()).await?;
+        println!("Re-encrypted silo {}", name);
+    }
+
+    // checkpoints are whole-store snapshots in the same format as the secrets file
+    match fs::read_dir(CHECKPOINTS_DIR) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "bin") {
+                    KVStore::new().rotate_master_key(&old_key, &new_key, path.to_str().unwrap_or_default()).await?;
+                    println!("Re-encrypted checkpoint {}", path.display());
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    if std::path::Path::new(USERS_FILE).exists() {
+        let users = UserStore::load(USERS_FILE, &old_key).await?;
+        users.save(&new_key).await?;
+        println!("Re-encrypted {} users", users.len().await);
+    }
+
+    if std::path::Path::new(TOKENS_FILE).exists() {
+        let revoked = TokenStore::new(TOKENS_FILE).rotate_master_key(&old_key, &new_key).await?;
+        println!("Re-encrypted the tokens file, revoking {} outstanding tokens", revoked);
+    }
+
+    for (path, plaintext) in &values {
+        let (nonce, ciphertext) = encrypt_data(&new_key, plaintext)?;
+        write_atomically(path, &[nonce, ciphertext].concat())?;
+    }
+    println!("Re-encrypted {} stored values", values.len());
+
+    fs::rename(&pending_key_file, KEY_FILE)?;
+    println!("Rotated master key {} -> {}", fingerprint(&old_key), fingerprint(&new_key));
+    Ok(())
+}
+
+pub async fn list_expired(verbose: bool, delete: bool) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+
+    let expired = store.expired_secrets().await;
+    if expired.is_empty() {
+        println!("No expired secrets");
+        return Ok(());
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let width = expired.iter().map(|(key, _)| key.len()).max().unwrap_or(0).max("KEY".len());
+    let mut header = format!("{:<width$}  {:<12}  {:<14}", "KEY", "EXPIRES_AT", "EXPIRED", width = width);
+    if verbose {
+        header.push_str("  SIZE");
+    }
+    println!("{}", header.trim_end());
+
+    for (key, secret) in &expired {
+        let expires_at = secret.expires_at.unwrap_or_default();
+        let age = format!("{} ago", format_age(now.saturating_sub(expires_at)));
+        let mut row = format!("{:<width$}  {:<12}  {:<14}", key, expires_at, age, width = width);
+        if verbose {
+            row.push_str(&format!("  {} bytes", secret.iv.len() + secret.encrypted_value.len()));
+        }
+        println!("{}", row.trim_end());
+    }
+
+    if delete {
+        let removed = store.sweep_expired().await;
+        store.persist(SECRETS_FILE, &master_key).await?;
+        println!("Deleted {} expired secrets", removed);
+    }
+    Ok(())
+}
+
+// everything known about one secret, read from the secrets file without decrypting the value;
+// disabled and expired secrets are shown too, since they are still stored
+pub async fn describe(key: &str) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+
+    let versions = store.list_versions(key).await;
+    let secret = versions
+        .first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Secret {} not found", key)))?;
+    let metadata = &secret.metadata;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let expires_at = match secret.expires_at {
+        Some(expires_at) => format_timestamp(expires_at),
+        None => "never".to_string(),
+    };
+    let fields = [
+        ("versions", versions.len().to_string()),
+        ("description", metadata.description.clone().unwrap_or_else(|| "-".to_string())),
+        ("tags", if metadata.tags.is_empty() { "-".to_string() } else { metadata.tags.join(", ") }),
+        ("created_at", format_timestamp(metadata.created_at)),
+        ("updated_at", format_timestamp(metadata.updated_at)),
+        ("expires_at", expires_at),
+        ("disabled", if secret.disabled { "yes" } else { "no" }.to_string()),
+        ("size_bytes", secret.encrypted_value.len().to_string()),
+        ("nonce_hex", hex::encode(&secret.iv)),
+    ];
+
+    let choice = if io::stdout().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never };
+    let mut stdout = StandardStream::stdout(choice);
+    stdout.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Cyan)))?;
+    writeln!(stdout, "{}", key)?;
+    for (label, value) in fields {
+        stdout.set_color(ColorSpec::new().set_dimmed(true))?;
+        write!(stdout, "  {:<12}", label)?;
+        let mut spec = ColorSpec::new();
+        if label == "disabled" && secret.disabled || label == "expires_at" && secret.is_expired(now) {
+            spec.set_fg(Some(Color::Red));
+        }
+        stdout.set_color(&spec)?;
+        writeln!(stdout, " {}", value)?;
+    }
+    stdout.reset()
+}
+
+// RFC 3339 in UTC, or "unknown" for the 0 that secrets stored before metadata carry
+fn format_timestamp(unix_seconds: u64) -> String {
+    if unix_seconds == 0 {
+        return "unknown".to_string();
+    }
+    time::OffsetDateTime::from_unix_timestamp(unix_seconds as i64)
+        .ok()
+        .and_then(|t| t.format(&time::format_description::well_known::Rfc3339).ok())
+        .unwrap_or_else(|| unix_seconds.to_string())
+}
+
+// the largest two units, e.g. "2d 3h" or "5m 10s"
+fn format_age(seconds: u64) -> String {
+    let (days, hours, minutes, secs) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+pub async fn generate_cert(
+    common_name: &str,
+    days: u64,
+    output_cert: &str,
+    output_key: &str,
+    algorithm: CertAlgorithm,
+    store_key: Option<&str>,
+) -> io::Result<()> {
+    let signature_algorithm = match algorithm {
+        CertAlgorithm::Ed25519 => &rcgen::PKCS_ED25519,
+        CertAlgorithm::EcdsaP256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+    };
+    let key_pair = rcgen::KeyPair::generate_for(signature_algorithm).map_err(io::Error::other)?;
+
+    let mut params = rcgen::CertificateParams::new(vec![common_name.to_string()]).map_err(io::Error::other)?;
+    params.distinguished_name.push(rcgen::DnType::CommonName, common_name);
+    params.not_before = time::OffsetDateTime::now_utc();
+    params.not_after = params.not_before + time::Duration::days(days as i64);
+    let cert = params.self_signed(&key_pair).map_err(io::Error::other)?;
+
+    let key_pem = key_pair.serialize_pem();
+    fs::write(output_cert, cert.pem())?;
+    write_key_file(output_key, key_pem.as_bytes())?;
+    println!("Wrote certificate to {} and private key to {}", output_cert, output_key);
+
+    if let Some(name) = store_key {
+        let master_key = get_or_create_key(KEY_FILE)?;
+        let store = KVStore::new();
+        if std::path::Path::new(SECRETS_FILE).exists() {
+            store.restore(SECRETS_FILE, &master_key).await?;
+        }
+        let (iv, encrypted_value) = encrypt_data(&master_key, key_pem.as_bytes())?;
+        store.set_secret(name.to_string(), iv, encrypted_value).await?;
+        store.persist(SECRETS_FILE, &master_key).await?;
+        println!("Stored the private key as {}", name);
+    }
+
+    let digest = digest(&SHA256, cert.der());
+    let fingerprint: Vec<String> = digest.as_ref().iter().map(|byte| format!("{:02X}", byte)).collect();
+    println!("SHA-256 fingerprint: {}", fingerprint.join(":"));
+    Ok(())
+}
+
+pub async fn export(output: &str) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Bundle passphrase: ")?);
+    if *passphrase != rpassword::prompt_password("Confirm passphrase: ")? {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Passphrases do not match"));
+    }
+
+    let bundle = store.export_bundle(&master_key, &passphrase).await?;
+    write_key_file(output, &bundle)?;
+    println!("Exported {} secrets to {}", store.list_keys().await.len(), output);
+    Ok(())
+}
+
+pub async fn import(input: &str, overwrite: bool) -> io::Result<()> {
+    let bundle = fs::read(input)?;
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Bundle passphrase: ")?);
+
+    let master_key = get_or_create_key(KEY_FILE)?;
+    let store = KVStore::new();
+    if std::path::Path::new(SECRETS_FILE).exists() {
+        store.restore(SECRETS_FILE, &master_key).await?;
+    }
+
+    let policy = if overwrite { MergePolicy::Overwrite } else { MergePolicy::SkipExisting };
+    let imported = store.import_bundle(&bundle, &passphrase, &master_key, policy).await?;
+    store.persist(SECRETS_FILE, &master_key).await?;
+    println!("Imported {} secrets from {}", imported, input);
+    Ok(())
+}
+
+// each field becomes a secret of its own; a lease becomes an expiry counted from now
+pub async fn import_vault_json(input: &str, vault_path: Option<&str>, overwrite: bool) -> io::Result<()> {
+    let json = fs::read(input)?;
+    let vault_path = match vault_path {
+        Some(vault_path) => vault_path,
+        None => std::path::Path::new(input).file_stem().and_then(|stem| stem.to_str()).unwrap_or(input),
+    };
+    let parsed = vault::parse_kv_get(&json, vault_path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let master_key = get_or_create_key(KEY_FILE)?;
+    let store = KVStore::new();
+    if std::path::Path::new(SECRETS_FILE).exists() {
+        store.restore(SECRETS_FILE, &master_key).await?;
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let expires_at = parsed.lease_duration.map(|seconds| now + seconds);
+    let mut imported = 0;
+    for (key, value) in &parsed.fields {
+        if !overwrite && store.contains_key(key).await {
+            println!("Skipped {}, it already exists", key);
+            continue;
+        }
+        let (iv, encrypted_value) = encrypt_data(&master_key, value)?;
+        store.set_secret_expiring(key.clone(), iv, encrypted_value, expires_at).await?;
+        imported += 1;
+    }
+    store.persist(SECRETS_FILE, &master_key).await?;
+
+    let version = match parsed.version {
+        KvVersion::V1 => "KV v1",
+        KvVersion::V2 => "KV v2",
+    };
+    println!("Imported {} of {} fields from {} ({}) under {}/", imported, parsed.fields.len(), input, version, vault_path.trim_end_matches('/'));
+    Ok(())
+}
+
+pub async fn checkpoint(label: &str) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+    store.checkpoint(label, &master_key).await?;
+    println!("Saved {} secrets to checkpoint {} in {}", store.list_keys().await.len(), label, CHECKPOINTS_DIR);
+    Ok(())
+}
+
+// replaces the secrets file with the checkpoint, under the same master key
+pub async fn restore_checkpoint(path: &str) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore_checkpoint(path, &master_key).await?;
+    store.persist(SECRETS_FILE, &master_key).await?;
+    println!("Restored {} secrets from {}", store.list_keys().await.len(), path);
+    Ok(())
+}
+
+// replays /secret writes and deletes oldest first into an empty store; entries whose key name was
+// hashed can't be replayed and are only counted. Expiry, tags and history are not in the log
+pub async fn restore(audit_log: &str, output: &str) -> io::Result<()> {
+    if std::path::Path::new(output).exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists, restore writes a new file", output)));
+    }
+    let master_key = load_key(KEY_FILE)?;
+    let mut entries = audit::entries_with_old(audit_log)?;
+    // the chain is already in order; a stable sort only matters for a clock that went backwards
+    entries.sort_by_key(|entry| entry.timestamp);
+
+    let store = KVStore::new();
+    let (mut replayed, mut hashed) = (0, 0);
+    for entry in entries.iter().filter(|entry| entry.allowed && (entry.action == audit::WRITE_ACTION || entry.action == audit::DELETE_ACTION)) {
+        if entry.resource.starts_with(audit::HASHED_KEY_PREFIX) {
+            hashed += 1;
+            continue;
+        }
+        if entry.action == audit::DELETE_ACTION {
+            store.delete_secret(&entry.resource).await;
+            replayed += 1;
+            continue;
+        }
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("write of {} at {} has no usable ciphertext", entry.resource, entry.timestamp));
+        let blob = entry.ciphertext.as_deref().and_then(|ciphertext| base64::decode(ciphertext, base64::Variant::Original).ok()).ok_or_else(invalid)?;
+        if blob.len() <= NONCE_LEN {
+            return Err(invalid());
+        }
+        let (iv, encrypted_value) = blob.split_at(NONCE_LEN);
+        // a log from before a rotate-key holds ciphertext the current key can't open
+        if decrypt_data(&master_key, iv, encrypted_value).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("write of {} at {} does not decrypt under the current master key", entry.resource, entry.timestamp),
+            ));
+        }
+        store.set_secret(entry.resource.clone(), iv.to_vec(), encrypted_value.to_vec()).await?;
+        replayed += 1;
+    }
+
+    store.persist(output, &master_key).await?;
+    println!("Replayed {} changes into {} ({} secrets)", replayed, output, store.list_keys().await.len());
+    if hashed > 0 {
+        println!("Skipped {} changes whose key names were hashed; only logs written with --log-sensitive-ops can be restored", hashed);
+    }
+    Ok(())
+}
+
+// silos are barn's contexts; the server loads them lazily, so a running one picks up the clone
+// on its first request for it. Grants on the source's secrets are copied to the clone's, which a
+// running server only sees after a restart
+pub async fn clone_silo(source: &str, target: &str, re_encrypt: bool, re_nonce: bool, pid_file: Option<&str>) -> io::Result<()> {
+    // every silo is encrypted under the master key, so there is no data key to replace
+    if re_encrypt {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--re-encrypt is not supported: there is no per-context DEK, every silo is under the master key (--re-nonce re-encrypts under fresh nonces)",
+        ));
+    }
+    // a running server holds the grants in memory and writes them all back on its next change,
+    // which would drop the ones copied here
+    if let Some(pid) = pid_file.and_then(running_pid) {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("the server is still running (PID {}), stop it before cloning a silo", pid),
+        ));
+    }
+
+    let master_key = load_key(KEY_FILE)?;
+    let silos = SiloManager::new("data");
+    let cloned = silos.clone_silo(source, target, &master_key, re_nonce).await?;
+    let how = if re_nonce { "re-encrypted under fresh nonces" } else { "copied" };
+    println!("Cloned silo {} to {} ({} secrets {})", source, target, cloned, how);
+
+    // no audit log is attached: a running server keeps the head of its chain in memory, so
+    // appending from here would fork it
+    let mut access = AccessControl::load(ACCESS_FILE)?;
+    let copied = access.copy_grants(source, target, None)?;
+    if copied > 0 {
+        access.save(ACCESS_FILE)?;
+    }
+    println!("Copied {} access grants", copied);
+    Ok(())
+}
+
+// the secrets file as it is on disk, under a fresh data key that only KMS can unwrap;
+// laid out as the wrapped key, nonce and ciphertext, each behind a u32 BE length
+#[cfg(feature = "kms")]
+pub async fn seal_vault(kms_key_id: &str) -> io::Result<()> {
+    let contents = fs::read(SECRETS_FILE)?;
+    let mut dek = Zeroizing::new(vec![0u8; 32]);
+    OsRng.fill_bytes(&mut dek);
+    let (nonce, ciphertext) = encrypt_data(&dek, &contents)?;
+
+    let client = kms_client().await;
+    let output = client
+        .encrypt()
+        .key_id(kms_key_id)
+        .plaintext(Blob::new(dek.to_vec()))
+        .send()
+        .await
+        .map_err(|e| io::Error::other(DisplayErrorContext(e).to_string()))?;
+    let wrapped_dek = output.ciphertext_blob().ok_or_else(|| io::Error::other("KMS returned no ciphertext"))?;
+
+    let mut sealed = Vec::new();
+    for part in [wrapped_dek.as_ref(), &nonce, &ciphertext] {
+        sealed.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        sealed.extend_from_slice(part);
+    }
+    write_key_file(SEALED_SECRETS_FILE, &sealed)?;
+    println!("Sealed {} to {} with KMS key {}", SECRETS_FILE, SEALED_SECRETS_FILE, kms_key_id);
+    Ok(())
+}
+
+// KMS finds the key from the wrapped data key itself, so kms_key_id only pins which one is expected
+#[cfg(feature = "kms")]
+pub async fn unseal_vault(kms_key_id: Option<&str>, force: bool) -> io::Result<()> {
+    if !force && std::path::Path::new(SECRETS_FILE).exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists, pass --force to replace it", SECRETS_FILE)));
+    }
+
+    let sealed = fs::read(SEALED_SECRETS_FILE)?;
+    let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} is truncated", SEALED_SECRETS_FILE));
+    let (wrapped_dek, rest) = split_length_prefixed(&sealed).ok_or_else(truncated)?;
+    let (nonce, rest) = split_length_prefixed(rest).ok_or_else(truncated)?;
+    let (ciphertext, _) = split_length_prefixed(rest).ok_or_else(truncated)?;
+
+    let client = kms_client().await;
+    let output = client
+        .decrypt()
+        .set_key_id(kms_key_id.map(str::to_string))
+        .ciphertext_blob(Blob::new(wrapped_dek))
+        .send()
+        .await
+        .map_err(|e| io::Error::other(DisplayErrorContext(e).to_string()))?;
+    let dek = Zeroizing::new(output.plaintext().ok_or_else(|| io::Error::other("KMS returned no plaintext"))?.as_ref().to_vec());
+
+    let contents = decrypt_data(&dek, nonce, ciphertext)?;
+    write_key_file(SECRETS_FILE, &contents)?;
+    println!("Restored {} from {}", SECRETS_FILE, SEALED_SECRETS_FILE);
+    Ok(())
+}
+
+// credentials and region come from the usual AWS environment variables, profile or instance role
+#[cfg(feature = "kms")]
+async fn kms_client() -> aws_sdk_kms::Client {
+    aws_sdk_kms::Client::new(&aws_config::load_from_env().await)
+}
+
+#[cfg(feature = "kms")]
+fn split_length_prefixed(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = data.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+// settings that should not end up in a terminal scrollback or a pasted bug report
+const REDACTED_SETTINGS: &[&str] = &["admin-token"];
+
+// barn reads no config file, so a value is either from the command line or clap's default
+pub fn print_config(matches: &ArgMatches) -> io::Result<()> {
+    let global = Cli::command();
+    let serve = ServeArgs::command();
+    let args = global.get_arguments().chain(serve.get_arguments());
+
+    for arg in args.filter(|arg| !matches!(arg.get_id(), "help" | "version")) {
+        let name = arg.get_long().unwrap_or_else(|| arg.get_id());
+        let source = match matches.value_source(arg.get_id()) {
+            Some(ValueSource::CommandLine) => "from CLI",
+            Some(ValueSource::EnvVariable) => "from env",
+            _ => "default",
+        };
+
+        if !arg.is_takes_value_set() {
+            println!("{} = {} # {}", name, matches.is_present(arg.get_id()), source);
+            continue;
+        }
+
+        let values: Vec<String> = match matches.get_raw(arg.get_id()) {
+            Some(values) => values.map(|value| toml_value(&value.to_string_lossy())).collect(),
+            None => {
+                println!("# {} is not set", name);
+                continue;
+            }
+        };
+        let value = if REDACTED_SETTINGS.contains(&name) {
+            "\"<redacted>\"".to_string()
+        } else if arg.is_multiple_values_set() || arg.is_use_value_delimiter_set() {
+            format!("[{}]", values.join(", "))
+        } else {
+            values.join(", ")
+        };
+        println!("{} = {} # {}", name, value, source);
+    }
+    Ok(())
+}
+
+// numbers stay bare; everything else becomes a quoted string
+fn toml_value(value: &str) -> String {
+    if value.parse::<i64>().is_ok() {
+        value.to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+// set-tag and remove-tag; only the tags change, the value is never decrypted
+pub async fn update_tag(key: &str, tag: &str, add: bool) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+
+    let tags = if add { store.add_tag(key, tag).await } else { store.remove_tag(key, tag).await };
+    let tags = tags.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Secret {} not found", key)))?;
+    store.persist(SECRETS_FILE, &master_key).await?;
+    println!("Tags of {}: {}", key, tags.join(", "));
+    Ok(())
+}
+
+// values are shell-quoted, so the file also works with `set -a; . ./.env`
+pub async fn export_env_file(output: &str, keys: &[String]) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+
+    // exporting everything leaves out disabled secrets, which can't be read, as template does
+    let keys = if keys.is_empty() {
+        let mut enabled = Vec::new();
+        for key in store.list_keys().await {
+            if !store.is_disabled(&key).await {
+                enabled.push(key);
+            }
+        }
+        enabled
+    } else {
+        keys.to_vec()
+    };
+    let generated_at = time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let mut contents = Zeroizing::new(format!("# Generated by Barn at {}\n", generated_at));
+
+    for key in &keys {
+        let secret = store
+            .get_secret(key)
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Secret {} not found", key)))?;
+        let plaintext = decrypt_data(&master_key, &secret.iv, &secret.encrypted_value)?;
+        let value = std::str::from_utf8(&plaintext)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Secret {} is not valid UTF-8", key)))?;
+
+        if value.contains('\n') {
+            eprintln!("Warning: {} contains a newline, some dotenv parsers will cut it short", key);
+        }
+        if key.is_empty() || key.starts_with(|c: char| c.is_ascii_digit()) || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            eprintln!("Warning: {} is not a valid environment variable name", key);
+        }
+        let quoted = shlex::try_quote(value)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Secret {} cannot be quoted: {}", key, e)))?;
+        contents.push_str(&format!("{}={}\n", key, quoted));
+    }
+
+    write_key_file(output, contents.as_bytes())?;
+    println!("Wrote {} secrets to {}", keys.len(), output);
+    Ok(())
+}
+
+// every secret is a Handlebars variable, so plain {{DB_PASSWORD}} substitution keeps working
+// alongside {{#if}}, {{#each}} and helpers; keys that are not identifiers are written {{[db/password]}}.
+// Output is not HTML-escaped, and without strict a missing variable renders as nothing
+pub async fn template(input: &str, output: &str, strict: bool) -> io::Result<()> {
+    let source = fs::read_to_string(input)?;
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+
+    let mut variables = serde_json::Map::new();
+    for key in store.list_keys().await {
+        let Some(secret) = store.get_secret(&key).await else { continue };
+        let plaintext = decrypt_data(&master_key, &secret.iv, &secret.encrypted_value)?;
+        let value = String::from_utf8(plaintext.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Secret {} is not valid UTF-8", key)))?;
+        variables.insert(key, value.into());
+    }
+
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(strict);
+    handlebars.register_escape_fn(handlebars::no_escape);
+    let rendered = Zeroizing::new(
+        handlebars
+            .render_template(&source, &variables)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", input, e)))?,
+    );
+
+    write_key_file(output, rendered.as_bytes())?;
+    println!("Rendered {} to {} with {} secrets available", input, output, variables.len());
+    Ok(())
+}
+
+// the plaintext only ever travels over the child's stdin, never its arguments or environment;
+// stdin is fed from another thread so a child that writes a lot before reading cannot deadlock
+pub async fn pipe(key: &str, command: &[String]) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+    let secret = store
+        .get_secret(key)
+        .await
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Secret {} not found", key)))?;
+    let plaintext = decrypt_data(&master_key, &secret.iv, &secret.encrypted_value)?;
+
+    let (program, args) = command.split_first().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No command given"))?;
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = std::thread::spawn(move || stdin.write_all(&plaintext));
+    let output = child.wait_with_output()?;
+    // a child that exits without reading all of stdin closes the pipe, which is not our error
+    match writer.join().expect("stdin writer panicked") {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+        _ => {}
+    }
+
+    io::stdout().write_all(&output.stdout)?;
+    io::stderr().write_all(&output.stderr)?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", program, output.status)));
+    }
+    Ok(())
+}
+
+// only the digests are printed, so a CI log never shows the value
+pub async fn assert(key: &str, expected_sha256: &str) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let store = KVStore::new();
+    store.restore(SECRETS_FILE, &master_key).await?;
+    let secret = store
+        .get_secret(key)
+        .await
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Secret {} not found", key)))?;
+    let plaintext = decrypt_data(&master_key, &secret.iv, &secret.encrypted_value)?;
+
+    let actual = hex::encode(digest(&SHA256, &plaintext).as_ref());
+    if actual.eq_ignore_ascii_case(expected_sha256) {
+        println!("PASS");
+        Ok(())
+    } else {
+        println!("FAIL: expected {} got {}", expected_sha256, actual);
+        std::process::exit(1);
+    }
+}
+
+// every key in a secrets file with its serialized entry, so any change to a key shows up
+async fn key_snapshot(file: &str, master_key: &[u8]) -> io::Result<HashMap<String, Vec<u8>>> {
+    let plaintext = KVStore::new().decrypt_file(file, master_key).await?;
+    let persisted: PersistedSecrets = serde_json::from_slice(&plaintext)?;
+    let mut snapshot = HashMap::new();
+    for (key, secret) in &persisted.secrets {
+        snapshot.insert(key.clone(), serde_json::to_vec(secret)?);
+    }
+    Ok(snapshot)
+}
+
+// the store writes its file under a temporary name and renames it into place, so the directory is
+// watched rather than the file, whose inode changes on every save; a close after writing or a
+// rename onto the file name triggers a reload
+pub async fn tail(file: &str) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let path = std::path::Path::new(file);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let mut previous = key_snapshot(file, &master_key).await?;
+
+    let (events, received) = std::sync::mpsc::channel();
+    let mut watcher = ::notify::recommended_watcher(events).map_err(io::Error::other)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive).map_err(io::Error::other)?;
+    for event in received {
+        let event = event.map_err(io::Error::other)?;
+        let written = matches!(event.kind, EventKind::Access(AccessKind::Close(AccessMode::Write)) | EventKind::Modify(ModifyKind::Name(_)));
+        if !written || !event.paths.iter().any(|changed| changed.file_name() == path.file_name()) {
+            continue;
+        }
+
+        // a file caught mid-write by some other writer is picked up on its next event
+        let current = match key_snapshot(file, &master_key).await {
+            Ok(current) => current,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", file, e);
+                continue;
+            }
+        };
+        let mut changed: Vec<&String> = current
+            .iter()
+            .filter(|(key, entry)| previous.get(*key) != Some(entry))
+            .map(|(key, _)| key)
+            .chain(previous.keys().filter(|key| !current.contains_key(*key)))
+            .collect();
+        changed.sort();
+        let mut stdout = io::stdout().lock();
+        for key in changed {
+            writeln!(stdout, "{}", key)?;
+        }
+        stdout.flush()?;
+        drop(stdout);
+        previous = current;
+    }
+    Ok(())
+}
+
+// the token only works against a server using the same master key
+pub async fn generate_token(key: &str, ttl_seconds: u64, max_uses: u32) -> io::Result<()> {
+    let master_key = load_key(KEY_FILE)?;
+    let token = TokenStore::new(TOKENS_FILE).issue(key, ttl_seconds, max_uses, &master_key).await?;
+    println!("{}", token);
+    eprintln!("Valid for {} seconds and {} use(s): GET /secret/{}?token={}", ttl_seconds, max_uses, key, token);
+    Ok(())
+}
+
+// the paths barn writes, matched by name inside data_dir; silos come from SiloManager
+const MANAGED_FILES: &[&str] = &[KEY_FILE, USERS_FILE, SECRETS_FILE, SEALED_SECRETS_FILE, SEAL_FILE, AUDIT_FILE, AUDIT_OLD_FILE, TOKENS_FILE, ACCESS_FILE, BCRYPT_COST_FILE, SELF_TEST_FILE];
+const MANAGED_DIRS: &[&str] = &[CHECKPOINTS_DIR, SHARES_DIR];
+
+fn file_name(path: &str) -> &str {
+    std::path::Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path)
+}
+
+// anything else in data_dir, such as a user's own files, is left alone
+pub fn wipe_cache(data_dir: &str, dry_run: bool, confirm: bool) -> io::Result<()> {
+    if !dry_run && !confirm {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Pass --confirm to delete, or --dry-run to only list the files"));
+    }
+
+    let silos_dir = SiloManager::new(data_dir).silos_dir().to_path_buf();
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(data_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            if MANAGED_DIRS.iter().any(|dir| file_name(dir) == name) || entry.path() == silos_dir {
+                collect_files(&entry.path(), &mut files)?;
+                dirs.push(entry.path());
+            }
+            continue;
+        }
+
+        // .tmp and .new are leftovers of an interrupted atomic write or key rotation
+        let base = name.strip_suffix(".tmp").or_else(|| name.strip_suffix(".new")).unwrap_or(&name);
+        if MANAGED_FILES.iter().any(|file| file_name(file) == base) || base.ends_with(".dat") {
+            files.push((entry.path(), entry.metadata()?.len()));
+        }
+    }
+    files.sort();
+
+    for (path, size) in &files {
+        println!("{} ({} bytes)", path.display(), size);
+    }
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+
+    if dry_run {
+        println!("Would remove {} files ({} bytes)", files.len(), total);
+        return Ok(());
+    }
+
+    for (path, _) in &files {
+        fs::remove_file(path)?;
+    }
+    for dir in &dirs {
+        fs::remove_dir_all(dir)?;
+    }
+    println!("Removed {} files ({} bytes)", files.len(), total);
+    Ok(())
+}
+
+// the share files --save-shares wrote; the warning uses the threshold from seal.json
+pub fn clean_shares(data_dir: &str, max_age_days: u64, dry_run: bool) -> io::Result<()> {
+    let shares_dir = std::path::Path::new(data_dir).join("shares");
+    let cutoff = SystemTime::now() - Duration::from_secs(max_age_days.saturating_mul(24 * 60 * 60));
+
+    let (old, kept): (Vec<_>, Vec<_>) = read_share_files(&shares_dir)?.into_iter().partition(|file| file.modified < cutoff);
+    let old: Vec<_> = old.into_iter().map(|file| file.path).collect();
+    let kept = kept.len();
+
+    let seal: Option<SealConfig> = fs::read(std::path::Path::new(data_dir).join("seal.json"))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+    if let Some(seal) = seal.filter(|seal| !old.is_empty() && kept < seal.shares.threshold as usize) {
+        eprintln!(
+            "Warning: only {} share files would be left, {} are needed to unseal",
+            kept, seal.shares.threshold
+        );
+    }
+
+    for path in &old {
+        if dry_run {
+            println!("Would remove {}", path.display());
+        } else {
+            fs::remove_file(path)?;
+            println!("Removed {}", path.display());
+        }
+    }
+    println!("{} share files older than {} days, {} kept", old.len(), max_age_days, kept);
+    Ok(())
+}
+
+// each share file goes to exactly one notifier, in file name order, so a config lists its
+// share holders in the order of the share-<x>.txt files --save-shares wrote
+pub fn export_shares(notifier_config: &str, data_dir: &str) -> io::Result<()> {
+    let config: NotifierConfig = toml::from_str(&fs::read_to_string(notifier_config)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", notifier_config, e)))?;
+
+    let shares_dir = std::path::Path::new(data_dir).join("shares");
+    // every file is checked before anything is sent, so a bad file doesn't leave a partial export
+    let files = read_share_files(&shares_dir)?;
+    if files.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no share files in {}", shares_dir.display())));
+    }
+    if files.len() != config.notifiers.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} share files but {} notifiers, each share needs exactly one", files.len(), config.notifiers.len()),
+        ));
+    }
+    let notifiers = config
+        .notifiers
+        .iter()
+        .map(|entry| notify::build(entry, config.smtp.as_ref()))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut failed = 0;
+    for (file, (entry, notifier)) in files.iter().zip(config.notifiers.iter().zip(&notifiers)) {
+        let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+        let share = Zeroizing::new(base64::encode(file.share.to_bytes(), base64::Variant::Original));
+        match notifier.notify(&name, &share) {
+            Ok(()) => println!("Sent {} to {}", name, entry.recipient()),
+            Err(e) => {
+                eprintln!("Failed to send {} to {}: {}", name, entry.recipient(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} of {} shares were not sent", failed, files.len())));
+    }
+    Ok(())
+}
+
+fn collect_files(dir: &std::path::Path, files: &mut Vec<(std::path::PathBuf, u64)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else {
+            files.push((entry.path(), entry.metadata()?.len()));
+        }
+    }
+    Ok(())
+}
+
+// only https URLs are fetched, so --insecure weakens certificate checks but never drops TLS
+pub async fn fetch(url: &str, store_key: &str, ttl: Option<u64>, insecure: bool) -> io::Result<()> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if parsed.scheme() != "https" {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only https URLs can be fetched"));
+    }
+    if insecure {
+        eprintln!("WARNING: --insecure skips certificate validation; the stored value may come from anyone between here and {}", parsed.host_str().unwrap_or(url));
+    }
+
+    let client = reqwest::Client::builder().danger_accept_invalid_certs(insecure).build().map_err(io::Error::other)?;
+    let response = client.get(parsed).send().await.map_err(io::Error::other)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(io::Error::other(format!("GET {} failed with {}", url, status)));
+    }
+    let max_age = response
+        .headers()
+        .get(reqwest::header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(cache_max_age);
+    let body = Zeroizing::new(response.bytes().await.map_err(io::Error::other)?.to_vec());
+
+    let master_key = get_or_create_key(KEY_FILE)?;
+    let store = KVStore::new();
+    if std::path::Path::new(SECRETS_FILE).exists() {
+        store.restore(SECRETS_FILE, &master_key).await?;
+    }
+    let ttl = ttl.or(max_age);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (iv, encrypted_value) = encrypt_data(&master_key, &body)?;
+    store.set_secret_expiring(store_key.to_string(), iv, encrypted_value, ttl.map(|ttl| now + ttl)).await?;
+    store.persist(SECRETS_FILE, &master_key).await?;
+
+    match ttl {
+        Some(ttl) => println!("Stored {} bytes as {}, expiring in {}", body.len(), store_key, format_age(ttl)),
+        None => println!("Stored {} bytes as {}", body.len(), store_key),
+    }
+    Ok(())
+}
+
+// the max-age directive of a Cache-Control header, in seconds
+fn cache_max_age(cache_control: &str) -> Option<u64> {
+    cache_control
+        .split(',')
+        .filter_map(|directive| directive.trim().strip_prefix("max-age="))
+        .find_map(|seconds| seconds.trim_matches('"').parse().ok())
+}
+
+// POSTs /admin/lock or /admin/unlock to a running server
+pub async fn set_write_lock(server: &str, admin_token: &str, locked: bool) -> io::Result<()> {
+    let action = if locked { "lock" } else { "unlock" };
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/{}", server.trim_end_matches('/'), action))
+        .header("X-Admin-Token", admin_token)
+        .send()
+        .await
+        .map_err(io::Error::other)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(io::Error::other(format!("{} failed with {}: {}", action, status, body)));
+    }
+
+    println!("{}", if locked { "Store locked for maintenance" } else { "Store unlocked" });
+    Ok(())
+}
@@ -1,14 +1,97 @@
-use actix_web::{web, HttpResponse, Responder, post};
-use chacha20poly1305::{XNonce, Key, aead::Aead};
+use actix_web::{web, HttpRequest, HttpResponse, Responder, ResponseError, delete, get, post, put};
+use barn::access_control::{user_id, Permission};
+use barn::audit::DELETE_ACTION;
+use barn::users::{authenticate_user, UserStore};
+use barn::kv_silo::{decrypt_data, encrypt_data, reconstruct_dek, write_value_file, KVStore, Secret, SecretWrite, ShareSerialization, SiloManager};
+use barn::login_throttle::LoginThrottle;
+use chacha20poly1305::Key;
 use serde::{Deserialize, Serialize};
 use std::fs::{self, OpenOptions};
-use std::io::{Read, Write};
+use std::io::Read;
+use std::sync::atomic::Ordering;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use ring::{constant_time, hmac};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sharks::Share;
+use sodiumoxide::{base64, hex};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::commands::fingerprint;
+use crate::errors::ApiError;
+use crate::middleware::client_ip;
+use crate::session::{issue_token, Session};
+use crate::{AppState, SealState, ACCESS_FILE, USERS_FILE};
+
+// only --expose-internal-errors lets the underlying error reach the client
+fn internal_error(state: &AppState, err: impl std::fmt::Display) -> HttpResponse {
+    let message = if state.expose_internal_errors {
+        err.to_string()
+    } else {
+        "Internal server error".to_string()
+    };
+    ApiError::Internal(message).error_response()
+}
+
+// like internal_error, but tells the client it was the value that could not be decrypted
+fn decryption_failed(state: &AppState, err: impl std::fmt::Display) -> HttpResponse {
+    let message = if state.expose_internal_errors {
+        err.to_string()
+    } else {
+        "Decryption failed".to_string()
+    };
+    ApiError::DecryptionFailed(message).error_response()
+}
+
+fn not_found(key: &str) -> HttpResponse {
+    ApiError::KeyNotFound { key: key.to_string() }.error_response()
+}
+
+// a disabled secret is still stored, so it is refused rather than reported missing
+fn disabled() -> HttpResponse {
+    ApiError::AccessDenied("secret disabled".to_string()).error_response()
+}
+
+fn sealed() -> HttpResponse {
+    ApiError::Sealed.error_response()
+}
+
+// the response every write endpoint sends while POST /admin/lock is in effect
+fn write_locked(state: &AppState) -> Option<HttpResponse> {
+    state
+        .write_locked
+        .is_locked()
+        .then(|| ApiError::WriteLocked.error_response())
+}
+
+// admin endpoints need X-Admin-Token to match --admin-token, and are off entirely without it;
+// returns the response to send when the caller is not an admin
+fn admin_denied(req: &HttpRequest, state: &AppState) -> Option<HttpResponse> {
+    let expected = match &state.admin_token {
+        Some(token) => token,
+        None => return Some(ApiError::AccessDenied("admin endpoints are disabled".to_string()).error_response()),
+    };
 
-use sodiumoxide::hex;
+    let given = req.headers().get("X-Admin-Token").map(|value| value.as_bytes()).unwrap_or_default();
+    constant_time::verify_slices_are_equal(given, expected.as_bytes())
+        .err()
+        .map(|_| ApiError::Unauthorized("invalid admin token".to_string()).error_response())
+}
 
-use crate::AppState;
+// under --require-login the session user needs a grant of at least `required` on path;
+// without it the server is open and grants are not consulted
+async fn access_denied(session: &Session, state: &AppState, path: &str, required: Permission) -> Option<HttpResponse> {
+    let username = match &session.username {
+        Some(username) if state.require_login => username,
+        _ => return None,
+    };
+    if state.access.read().await.has_access(user_id(username), path, required) {
+        return None;
+    }
+    Some(ApiError::AccessDenied(format!("{:?} access to {} is not granted", required, path)).error_response())
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct StoreRequest {
@@ -17,30 +100,55 @@ pub struct StoreRequest {
 }
 
 #[post("/store")]
-async fn store(data: web::Json<StoreRequest>, state: web::Data<AppState>) -> impl Responder {
-    let data_dir = "data";
-    fs::create_dir_all(data_dir).expect("Failed to create data directory");
+async fn store(data: web::Json<StoreRequest>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = write_locked(&state) {
+        return response;
+    }
+    if let Some(response) = access_denied(&session, &state, &data.key, Permission::Write).await {
+        return response;
+    }
+    let master_key = state.master_key().await;
+    // anonymous unless a session token was sent
+    if let Err(e) = state.audit.record_key(session.username.as_deref(), "store", &data.key, master_key.is_some(), Some(data.value.as_bytes())) {
+        return internal_error(&state, e);
+    }
+    let master_key = match master_key {
+        Some(key) => key,
+        None => return sealed(),
+    };
 
-    let mut nonce = vec![0u8; 24];
-    OsRng.fill_bytes(&mut nonce);
-    let nonce = XNonce::from_slice(&nonce);
+    if let Err(e) = write_value(&master_key, &data.key, &data.value) {
+        return internal_error(&state, e);
+    }
 
-    let ciphertext = match state.encryptor.encrypt(nonce, data.value.as_bytes()) {
-        Ok(c) => c,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
+    HttpResponse::Ok().body("Key-value pair stored successfully")
+}
+
+// values live in data/{key}.dat as the 24-byte nonce followed by the ciphertext
+fn write_value(master_key: &[u8], key: &str, value: &str) -> std::io::Result<()> {
+    let data_dir = "data";
+    fs::create_dir_all(data_dir)?;
+
+    write_value_file(format!("{}/{}.dat", data_dir, key), master_key, value.as_bytes())
+}
 
-    let file_path = format!("{}/{}.dat", data_dir, data.key);
-    let mut file = match OpenOptions::new().write(true).create(true).open(&file_path) {
+// None when nothing was stored under key
+fn read_value(master_key: &[u8], key: &str) -> std::io::Result<Option<Zeroizing<String>>> {
+    let mut file = match OpenOptions::new().read(true).open(format!("data/{}.dat", key)) {
         Ok(file) => file,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
     };
 
-    if file.write_all(&nonce).is_err() || file.write_all(&ciphertext).is_err() {
-        return HttpResponse::InternalServerError().finish();
-    }
+    let mut nonce = vec![0u8; 24];
+    file.read_exact(&mut nonce)?;
+    let mut ciphertext = Vec::new();
+    file.read_to_end(&mut ciphertext)?;
 
-    HttpResponse::Ok().body("Key-value pair stored successfully")
+    let plaintext = decrypt_data(master_key, &nonce, &ciphertext)?;
+    std::str::from_utf8(&plaintext)
+        .map(|text| Some(Zeroizing::new(text.to_string())))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
 }
 
 
@@ -67,36 +175,549 @@ pub struct LoadRequest {
 }
 
 #[post("/load")]
-async fn load(data: web::Json<LoadRequest>, state: web::Data<AppState>) -> impl Responder {
-    let data_dir = "data";
-    fs::create_dir_all(data_dir).expect("Failed to create data directory");
+async fn load(data: web::Json<LoadRequest>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = access_denied(&session, &state, &data.key, Permission::Read).await {
+        return response;
+    }
+    let master_key = state.master_key().await;
+    if let Err(e) = state.audit.record_key(session.username.as_deref(), "load", &data.key, master_key.is_some(), None) {
+        return internal_error(&state, e);
+    }
+    let master_key = match master_key {
+        Some(key) => key,
+        None => return sealed(),
+    };
 
-    let file_path = format!("{}/{}.dat", data_dir, data.key);
-    let mut file = match OpenOptions::new().read(true).open(&file_path) {
-        Ok(file) => file,
-        Err(_) => return HttpResponse::NotFound().body("File not found"),
+    match read_value(&master_key, &data.key) {
+        Ok(Some(text)) => HttpResponse::Ok().body(text.to_string()),
+        Ok(None) => not_found(&data.key),
+        Err(e) => internal_error(&state, e),
+    }
+}
+
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+// each value gets a fresh random key, returned in the same order as the values; nobody can
+// hold a grant on a key that doesn't exist yet, so there is no grant to check
+#[post("/store/batch")]
+async fn store_batch(values: web::Json<Vec<String>>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = write_locked(&state) {
+        return response;
+    }
+    let master_key = state.master_key().await;
+    let tokens: Vec<String> = values.iter().map(|_| Uuid::new_v4().to_string()).collect();
+    for (token, value) in tokens.iter().zip(values.iter()) {
+        if let Err(e) = state.audit.record_key(session.username.as_deref(), "store", token, master_key.is_some(), Some(value.as_bytes())) {
+            return internal_error(&state, e);
+        }
+    }
+    let master_key = match master_key {
+        Some(key) => key,
+        None => return sealed(),
     };
 
-    let mut nonce = vec![0u8; 24];
-    if let Err(_) = file.read_exact(&mut nonce) {
-        return HttpResponse::InternalServerError().finish();
+    for (token, value) in tokens.iter().zip(values.iter()) {
+        if let Err(e) = write_value(&master_key, token, value) {
+            return internal_error(&state, e);
+        }
     }
 
-    let mut ciphertext = Vec::new();
-    if let Err(_) = file.read_to_end(&mut ciphertext) {
-        return HttpResponse::InternalServerError().finish();
+    HttpResponse::Ok().json(tokens)
+}
+
+// unknown tokens come back as null rather than failing the whole batch
+#[post("/load/batch")]
+async fn load_batch(tokens: web::Json<Vec<String>>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    for token in tokens.iter() {
+        if let Some(response) = access_denied(&session, &state, token, Permission::Read).await {
+            return response;
+        }
+    }
+    let master_key = state.master_key().await;
+    for token in tokens.iter() {
+        if let Err(e) = state.audit.record_key(session.username.as_deref(), "load", token, master_key.is_some(), None) {
+            return internal_error(&state, e);
+        }
+    }
+    let master_key = match master_key {
+        Some(key) => key,
+        None => return sealed(),
+    };
+
+    let mut values = Vec::with_capacity(tokens.len());
+    for token in tokens.iter() {
+        match read_value(&master_key, token) {
+            Ok(value) => values.push(value.map(|text| text.to_string())),
+            Err(e) => return internal_error(&state, e),
+        }
+    }
+
+    HttpResponse::Ok().json(values)
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+#[derive(Deserialize)]
+pub struct PutSecretQuery {
+    // seconds until the secret expires; it never does when absent
+    pub ttl: Option<u64>,
+}
+
+#[put("/secret/{key}")]
+async fn put_secret(path: web::Path<String>, query: web::Query<PutSecretQuery>, body: web::Bytes, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = write_locked(&state) {
+        return response;
+    }
+    if let Some(response) = access_denied(&session, &state, &path, Permission::Write).await {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    let (iv, encrypted_value) = match encrypt_data(&master_key, &body) {
+        Ok(encrypted) => encrypted,
+        Err(e) => return internal_error(&state, e),
+    };
+    if let Err(e) = kv.check_key(&path) {
+        return ApiError::InvalidRequest(e.to_string()).error_response();
+    }
+    if let Err(e) = state.audit.record_write(session.username.as_deref(), &path, &body, &iv, &encrypted_value) {
+        return internal_error(&state, e);
+    }
+    let expires_at = query.ttl.map(|ttl| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) + ttl
+    });
+    let write = match kv.set_secret_expiring(path.into_inner(), iv, encrypted_value, expires_at).await {
+        Ok(write) => write,
+        Err(e) => return internal_error(&state, e),
+    };
+
+    if let Err(e) = kv.persist(&state.secrets_file, &master_key).await {
+        return internal_error(&state, e);
+    }
+
+    // 201 tells a new key apart from an overwritten one
+    match write {
+        SecretWrite::Created => HttpResponse::Created().body("Secret stored successfully"),
+        SecretWrite::Updated => HttpResponse::Ok().body("Secret stored successfully"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    token: Option<String>,
+}
+
+// a ?token= from `barn generate-token` stands in for the session, so a bare curl can read the secret;
+// the token is only used up once the value is in hand, so a 404 or a disabled secret doesn't burn it
+#[get("/secret/{key}")]
+async fn get_secret(path: web::Path<String>, query: web::Query<TokenQuery>, kv: web::Data<KVStore>, session: Result<Session, actix_web::Error>, state: web::Data<AppState>) -> impl Responder {
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    let invalid_token = || ApiError::Unauthorized("invalid, expired or used up token".to_string()).error_response();
+    match &query.token {
+        Some(token) => match state.tokens.is_valid(&path, token, &master_key).await {
+            Ok(true) => {}
+            Ok(false) => return invalid_token(),
+            Err(e) => return internal_error(&state, e),
+        },
+        None => match session {
+            Ok(session) => {
+                if let Some(response) = access_denied(&session, &state, &path, Permission::Read).await {
+                    return response;
+                }
+            }
+            Err(e) => return e.error_response(),
+        },
+    }
+    let value = match kv.get_secret(&path).await {
+        Some(secret) => match decrypt_data(&master_key, &secret.iv, &secret.encrypted_value) {
+            Ok(value) => value,
+            Err(e) => return decryption_failed(&state, e),
+        },
+        None if kv.is_disabled(&path).await => return disabled(),
+        None => match env_fallback(&state, &path) {
+            Some(value) => value,
+            None => return not_found(&path),
+        },
+    };
+
+    // a concurrent read may have used the last use up since is_valid
+    if let Some(token) = &query.token {
+        match state.tokens.redeem(&path, token, &master_key).await {
+            Ok(true) => {}
+            Ok(false) => return invalid_token(),
+            Err(e) => return internal_error(&state, e),
+        }
+    }
+    HttpResponse::Ok().body(value.to_vec())
+}
+
+#[derive(Deserialize)]
+pub struct TagRequest {
+    pub tag: String,
+}
+
+#[post("/secret/{key}/tags")]
+async fn add_secret_tag(path: web::Path<String>, data: web::Json<TagRequest>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if data.tag.is_empty() {
+        return ApiError::InvalidRequest("tag must not be empty".to_string()).error_response();
+    }
+    update_tags(&path, kv.add_tag(&path, &data.tag), &kv, &session, &state).await
+}
+
+#[delete("/secret/{key}/tags/{tag}")]
+async fn remove_secret_tag(path: web::Path<(String, String)>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    let (key, tag) = path.into_inner();
+    update_tags(&key, kv.remove_tag(&key, &tag), &kv, &session, &state).await
+}
+
+// the change is only made once the write lock, grants and seal allow it, then saved like any other write
+async fn update_tags(key: &str, change: impl std::future::Future<Output = Option<Vec<String>>>, kv: &KVStore, session: &Session, state: &AppState) -> HttpResponse {
+    if let Some(response) = write_locked(state) {
+        return response;
+    }
+    if let Some(response) = access_denied(session, state, key, Permission::Write).await {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    let tags = match change.await {
+        Some(tags) => tags,
+        None => return not_found(key),
+    };
+
+    if let Err(e) = kv.persist(&state.secrets_file, &master_key).await {
+        return internal_error(state, e);
+    }
+    HttpResponse::Ok().json(serde_json::json!({ "key": key, "tags": tags }))
+}
+
+// nothing is decrypted, so the audit log records an exists_check rather than a read
+#[get("/secret/{key}/exists")]
+async fn secret_exists(path: web::Path<String>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = access_denied(&session, &state, &path, Permission::Read).await {
+        return response;
+    }
+    let unsealed = state.master_key().await.is_some();
+    if let Err(e) = state.audit.record_key(session.username.as_deref(), "exists_check", &path, unsealed, None) {
+        return internal_error(&state, e);
+    }
+    if !unsealed {
+        return sealed();
+    }
+
+    if kv.contains_key(&path).await {
+        HttpResponse::Ok().finish()
+    } else {
+        not_found(&path)
+    }
+}
+
+// only variables under this prefix are served, so the fallback can't hand out the rest of the
+// server's environment (cloud credentials and the like)
+const ENV_FALLBACK_PREFIX: &str = "BARN_SECRET_";
+
+// only consulted once --fallback-to-env has kicked in, see AppState::load_secrets
+fn env_fallback(state: &AppState, key: &str) -> Option<Zeroizing<Vec<u8>>> {
+    if !state.using_env_fallback.load(Ordering::Relaxed) {
+        return None;
+    }
+    let value = std::env::var(format!("{}{}", ENV_FALLBACK_PREFIX, key)).ok()?;
+    log::warn!("Secret {} read from the environment, the secrets file is unavailable", key);
+    Some(Zeroizing::new(value.into_bytes()))
+}
+
+#[delete("/secret/{key}")]
+async fn delete_secret(path: web::Path<String>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = write_locked(&state) {
+        return response;
+    }
+    if let Some(response) = access_denied(&session, &state, &path, Permission::Write).await {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    if let Err(e) = state.audit.record_key(session.username.as_deref(), DELETE_ACTION, &path, true, None) {
+        return internal_error(&state, e);
+    }
+    if !kv.delete_secret(&path).await {
+        return not_found(&path);
+    }
+
+    if let Err(e) = kv.persist(&state.secrets_file, &master_key).await {
+        return internal_error(&state, e);
+    }
+
+    HttpResponse::Ok().body("Secret deleted successfully")
+}
+
+// the body is a JSON array of keys; the answer maps each one to whether it existed,
+// and the store is saved once for the whole batch
+#[delete("/secrets/batch")]
+async fn batch_delete_secrets(data: web::Json<Vec<String>>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = write_locked(&state) {
+        return response;
+    }
+    for key in data.iter() {
+        if let Some(response) = access_denied(&session, &state, key, Permission::Write).await {
+            return response;
+        }
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+
+    for key in data.iter() {
+        if let Err(e) = state.audit.record_key(session.username.as_deref(), DELETE_ACTION, key, true, None) {
+            return internal_error(&state, e);
+        }
+    }
+    let keys: Vec<&str> = data.iter().map(String::as_str).collect();
+    let deleted = kv.batch_delete(&keys).await;
+    if deleted.values().any(|&existed| existed) {
+        if let Err(e) = kv.persist(&state.secrets_file, &master_key).await {
+            return internal_error(&state, e);
+        }
+    }
+
+    HttpResponse::Ok().json(deleted)
+}
+
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueFormat {
+    #[default]
+    Base64,
+    Hex,
+    Utf8,
+}
+
+#[derive(Deserialize)]
+pub struct ValueQuery {
+    #[serde(default)]
+    pub format: ValueFormat,
+}
+
+#[get("/secret/{key}/value")]
+async fn secret_value(path: web::Path<String>, query: web::Query<ValueQuery>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = access_denied(&session, &state, &path, Permission::Read).await {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    let plaintext = match kv.get_secret(&path).await {
+        Some(secret) => match decrypt_data(&master_key, &secret.iv, &secret.encrypted_value) {
+            Ok(value) => value,
+            Err(e) => return decryption_failed(&state, e),
+        },
+        None if kv.is_disabled(&path).await => return disabled(),
+        None => match env_fallback(&state, &path) {
+            Some(value) => value,
+            None => return not_found(&path),
+        },
+    };
+
+    // the content type tells the client how to turn the body back into bytes
+    match query.format {
+        ValueFormat::Base64 => HttpResponse::Ok()
+            .content_type("text/plain; encoding=base64")
+            .body(base64::encode(&plaintext, base64::Variant::Original)),
+        ValueFormat::Hex => HttpResponse::Ok()
+            .content_type("text/plain; encoding=hex")
+            .body(hex::encode(&plaintext)),
+        ValueFormat::Utf8 => match std::str::from_utf8(&plaintext) {
+            Ok(text) => HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(text.to_string()),
+            Err(_) => ApiError::InvalidValue("value is not valid UTF-8".to_string()).error_response(),
+        },
+    }
+}
+
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignatureAlgorithm {
+    #[default]
+    HmacSha256,
+    Blake3,
+}
+
+// signatures are keyed with subkeys so the raw master key never feeds either MAC directly
+const BLAKE3_CONTEXT: &str = "barn secret signature v1";
+const HMAC_CONTEXT: &[u8] = b"barn secret signature hmac-sha256 v1";
+
+// HMAC-SHA256 of the context under the master key, the same derivation as download tokens use
+fn hmac_subkey(master_key: &[u8]) -> hmac::Key {
+    let derived = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, master_key), HMAC_CONTEXT);
+    hmac::Key::new(hmac::HMAC_SHA256, derived.as_ref())
+}
+
+impl SignatureAlgorithm {
+    // keyed MAC over the stored nonce + ciphertext, never over the plaintext
+    fn mac(self, key: &[u8], blob: &[u8]) -> Vec<u8> {
+        match self {
+            SignatureAlgorithm::HmacSha256 => hmac::sign(&hmac_subkey(key), blob).as_ref().to_vec(),
+            SignatureAlgorithm::Blake3 => {
+                let key = blake3::derive_key(BLAKE3_CONTEXT, key);
+                blake3::keyed_hash(&key, blob).as_bytes().to_vec()
+            }
+        }
+    }
+
+    // both comparisons are constant time
+    fn verify(self, key: &[u8], blob: &[u8], signature: &[u8]) -> bool {
+        match self {
+            SignatureAlgorithm::HmacSha256 => hmac::verify(&hmac_subkey(key), blob, signature).is_ok(),
+            SignatureAlgorithm::Blake3 => {
+                let key = blake3::derive_key(BLAKE3_CONTEXT, key);
+                blake3::Hash::from_slice(signature).is_ok_and(|signature| signature == blake3::keyed_hash(&key, blob))
+            }
+        }
     }
+}
 
-    let nonce = XNonce::from_slice(&nonce);
-    let plaintext = match state.encryptor.decrypt(nonce, ciphertext.as_ref()) {
-        Ok(p) => p,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+#[derive(Deserialize)]
+pub struct SignQuery {
+    #[serde(default)]
+    pub algorithm: SignatureAlgorithm,
+}
+
+#[derive(Serialize)]
+pub struct SignResponse {
+    pub key: String,
+    pub signature: String,
+    pub algorithm: SignatureAlgorithm,
+    pub signed_at: u64,
+}
+
+#[derive(Deserialize)]
+pub struct VerifySignatureRequest {
+    pub signature: String,
+    #[serde(default)]
+    pub algorithm: SignatureAlgorithm,
+}
+
+// the signed bytes are the stored iv followed by the ciphertext
+fn signed_bytes(secret: &Secret) -> Vec<u8> {
+    [secret.iv.as_slice(), secret.encrypted_value.as_slice()].concat()
+}
+
+#[get("/secret/{key}/sign")]
+async fn sign_secret(path: web::Path<String>, query: web::Query<SignQuery>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = access_denied(&session, &state, &path, Permission::Read).await {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    let key = path.into_inner();
+    let blob = match kv.get_secret(&key).await {
+        Some(secret) => signed_bytes(&secret),
+        None if kv.is_disabled(&key).await => return disabled(),
+        None => return not_found(&key),
     };
 
-    match String::from_utf8(plaintext) {
-        Ok(text) => HttpResponse::Ok().body(text),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to convert plaintext to string"),
+    let signed_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let signature = hex::encode(query.algorithm.mac(&master_key, &blob));
+
+    HttpResponse::Ok().json(SignResponse { key, signature, algorithm: query.algorithm, signed_at })
+}
+
+#[post("/secret/{key}/verify-signature")]
+async fn verify_secret_signature(path: web::Path<String>, data: web::Json<VerifySignatureRequest>, kv: web::Data<KVStore>, session: Session, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = access_denied(&session, &state, &path, Permission::Read).await {
+        return response;
     }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    let blob = match kv.get_secret(&path).await {
+        Some(secret) => signed_bytes(&secret),
+        None if kv.is_disabled(&path).await => return disabled(),
+        None => return not_found(&path),
+    };
+
+    let signature = match hex::decode(&data.signature) {
+        Ok(signature) => signature,
+        Err(_) => return ApiError::InvalidRequest("signature must be hex encoded".to_string()).error_response(),
+    };
+
+    let valid = data.algorithm.verify(&master_key, &blob, &signature);
+    HttpResponse::Ok().json(serde_json::json!({ "valid": valid }))
 }
 
 
@@ -145,10 +766,569 @@ async fn generate_key() -> impl Responder {
 
 
 
-// #[post("/login")]
-// async fn login() -> impl Responder {
-//     let key_bytes = match hex::decode(&hex_key.0) {
-//         Ok(bytes) => bytes;
-//         Err(_) => return HttpResponse::BadRequest().body("Invalid key format.");
-//     }
-// }
\ No newline at end of file
+#[get("/stats")]
+async fn stats(kv: web::Data<KVStore>, state: web::Data<AppState>) -> impl Responder {
+    let in_flight = state.max_concurrent_requests - state.request_slots.available_permits();
+    HttpResponse::Ok().json(serde_json::json!({
+        "in_flight_requests": in_flight,
+        "invalidated_at": kv.invalidated_at(),
+        "self_test_failures_total": state.self_test_failures.load(Ordering::Relaxed),
+    }))
+}
+
+#[get("/stats/tags")]
+async fn tag_stats(kv: web::Data<KVStore>) -> impl Responder {
+    let counts: Vec<_> = kv
+        .count_by_tag()
+        .await
+        .into_iter()
+        .map(|(tag, count)| serde_json::json!({ "tag": tag, "count": count }))
+        .collect();
+    HttpResponse::Ok().json(counts)
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+#[get("/version")]
+async fn version() -> impl Responder {
+    let features: Vec<&str> = [("kms", cfg!(feature = "kms"))]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("BARN_GIT_SHA"),
+        "build_date": env!("BARN_BUILD_DATE"),
+        "features": features,
+    }))
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+#[get("/admin/store-info")]
+async fn store_info(req: HttpRequest, kv: web::Data<KVStore>, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = admin_denied(&req, &state) {
+        return response;
+    }
+
+    let info = kv.info().await;
+
+    // secrets carry no lock, so there are never any locked ones
+    HttpResponse::Ok().json(serde_json::json!({
+        "total_secrets": info.total_secrets,
+        "expired_secrets": info.expired_secrets,
+        "locked_secrets": 0,
+        "disabled_secrets": info.disabled_secrets,
+        "total_bytes": info.total_bytes,
+        "oldest_secret": info.oldest_secret,
+        "newest_secret": info.newest_secret,
+        "last_save": info.last_save,
+        "save_errors_since_start": info.save_errors_since_start,
+        "cipher": kv.algorithm().cipher().name(),
+    }))
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+fn seal_status_body(seal: &SealState) -> serde_json::Value {
+    match seal {
+        SealState::Sealed { config, submitted } => serde_json::json!({
+            "sealed": true,
+            "submitted": submitted.len(),
+            "threshold": config.shares.threshold,
+            "total": config.shares.total,
+        }),
+        SealState::Unsealed { .. } => serde_json::json!({ "sealed": false }),
+        SealState::FromShares { .. } => serde_json::json!({ "sealed": false, "reload_shares_on_access": true }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnsealRequest {
+    pub shares: Vec<String>,
+}
+
+#[post("/unseal")]
+async fn unseal(data: web::Json<UnsealRequest>, kv: web::Data<KVStore>, state: web::Data<AppState>) -> impl Responder {
+    let mut seal = state.seal.write().await;
+    let (config, submitted) = match &mut *seal {
+        SealState::Sealed { config, submitted } => (config, submitted),
+        SealState::Unsealed { .. } | SealState::FromShares { .. } => return HttpResponse::Ok().json(seal_status_body(&seal)),
+    };
+
+    for encoded in &data.shares {
+        let share = base64::decode(encoded, base64::Variant::Original)
+            .ok()
+            .and_then(|bytes| Share::from_bytes(&bytes).ok());
+        let share = match share {
+            Some(share) => share,
+            None => return ApiError::InvalidRequest("shares must be base64 encoded key shares".to_string()).error_response(),
+        };
+
+        // the first byte is the share's x coordinate, the same share twice must not count twice
+        let x = share.to_bytes()[0];
+        if !submitted.iter().any(|existing| existing.to_bytes()[0] == x) {
+            submitted.push(share);
+        }
+    }
+
+    if submitted.len() < config.shares.threshold as usize {
+        return HttpResponse::Ok().json(seal_status_body(&seal));
+    }
+
+    // any set of shares interpolates to some key, so check it is the one the store was sealed with
+    let master_key = match reconstruct_dek(submitted, config.shares.threshold) {
+        Ok(key) if fingerprint(&key) == config.fingerprint => key,
+        _ => {
+            submitted.clear();
+            return ApiError::InvalidRequest("the submitted shares do not rebuild the master key, start again".to_string()).error_response();
+        }
+    };
+
+    if let Err(e) = state.load_secrets(&kv, &master_key).await {
+        return internal_error(&state, e);
+    }
+
+    let users = match UserStore::load(USERS_FILE, &master_key).await {
+        Ok(users) => users,
+        Err(e) => return internal_error(&state, e),
+    };
+    *state.users.write().await = Some(users);
+
+    *seal = SealState::Unsealed { master_key };
+    HttpResponse::Ok().json(seal_status_body(&seal))
+}
+
+#[get("/seal-status")]
+async fn seal_status(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(seal_status_body(&*state.seal.read().await))
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+#[post("/admin/invalidate-cache")]
+async fn invalidate_cache(req: HttpRequest, kv: web::Data<KVStore>, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = admin_denied(&req, &state) {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+
+    if let Err(e) = kv.invalidate_cache(&state.secrets_file, &master_key).await {
+        return internal_error(&state, e);
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({ "invalidated_at": kv.invalidated_at() }))
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+// bad silo names and over-long keys are the caller's fault, anything else is ours
+fn silo_error(state: &AppState, e: std::io::Error) -> HttpResponse {
+    if e.kind() == std::io::ErrorKind::InvalidInput {
+        return ApiError::InvalidRequest(e.to_string()).error_response();
+    }
+    internal_error(state, e)
+}
+
+#[get("/silos")]
+async fn list_silos(_session: Session, silos: web::Data<SiloManager>, state: web::Data<AppState>) -> impl Responder {
+    match silos.list_silos().await {
+        Ok(names) => HttpResponse::Ok().json(serde_json::json!({ "silos": names })),
+        Err(e) => internal_error(&state, e),
+    }
+}
+
+// grants on a silo's secrets are held on "{silo}/{key}"
+fn silo_path(silo: &str, key: &str) -> String {
+    format!("{}/{}", silo, key)
+}
+
+#[put("/silo/{silo}/secret/{key}")]
+async fn put_silo_secret(path: web::Path<(String, String)>, body: web::Bytes, session: Session, silos: web::Data<SiloManager>, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = write_locked(&state) {
+        return response;
+    }
+    let (name, key) = path.into_inner();
+    if let Some(response) = access_denied(&session, &state, &silo_path(&name, &key), Permission::Write).await {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+
+    let silo = match silos.get_or_create_silo(&name, &master_key).await {
+        Ok(silo) => silo,
+        Err(e) => return silo_error(&state, e),
+    };
+    let (iv, encrypted_value) = match encrypt_data(&master_key, &body) {
+        Ok(encrypted) => encrypted,
+        Err(e) => return internal_error(&state, e),
+    };
+    if let Err(e) = silo.set_secret(key, iv, encrypted_value).await {
+        return silo_error(&state, e);
+    }
+    if let Err(e) = silos.persist_silo(&name, &master_key).await {
+        return internal_error(&state, e);
+    }
+
+    HttpResponse::Ok().body("Secret stored successfully")
+}
+
+#[derive(Deserialize)]
+pub struct FallbackQuery {
+    // comma-separated silos to try in order when the requested one lacks the key
+    fallback: Option<String>,
+}
+
+// X-Fallback-Context names the silo the value came from, which differs from the
+// requested one only when a ?fallback= silo answered
+#[get("/silo/{silo}/secret/{key}")]
+async fn get_silo_secret(path: web::Path<(String, String)>, query: web::Query<FallbackQuery>, session: Session, silos: web::Data<SiloManager>, state: web::Data<AppState>) -> impl Responder {
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+    let (name, key) = path.into_inner();
+
+    let fallbacks = query.fallback.as_deref().unwrap_or_default();
+    let names: Vec<&str> = std::iter::once(name.as_str())
+        .chain(fallbacks.split(',').map(str::trim).filter(|name| !name.is_empty()))
+        .collect();
+    // any silo in the chain may answer, so each needs a read grant
+    for name in &names {
+        if let Some(response) = access_denied(&session, &state, &silo_path(name, &key), Permission::Read).await {
+            return response;
+        }
+    }
+
+    // silos that were never created are skipped rather than failing the lookup
+    let mut chain = Vec::new();
+    for name in &names {
+        match silos.get_silo(name, &master_key).await {
+            Ok(Some(silo)) => chain.push((*name, silo)),
+            Ok(None) => {}
+            Err(e) => return silo_error(&state, e),
+        }
+    }
+
+    let found = match chain.split_first() {
+        Some(((_, first), rest)) => {
+            let rest: Vec<&KVStore> = rest.iter().map(|(_, silo)| silo.as_ref()).collect();
+            first.get_secret_with_fallback_index(&key, &rest, &master_key).await
+        }
+        None => None,
+    };
+    match found {
+        Some((i, secret)) => match decrypt_data(&master_key, &secret.iv, &secret.encrypted_value) {
+            Ok(value) => HttpResponse::Ok().insert_header(("X-Fallback-Context", chain[i].0)).body(value.to_vec()),
+            Err(e) => decryption_failed(&state, e),
+        },
+        None => not_found(&key),
+    }
+}
+
+#[delete("/silo/{silo}/secret/{key}")]
+async fn delete_silo_secret(path: web::Path<(String, String)>, session: Session, silos: web::Data<SiloManager>, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = write_locked(&state) {
+        return response;
+    }
+    let (name, key) = path.into_inner();
+    if let Some(response) = access_denied(&session, &state, &silo_path(&name, &key), Permission::Write).await {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+
+    let deleted = match silos.get_silo(&name, &master_key).await {
+        Ok(Some(silo)) => silo.delete_secret(&key).await,
+        Ok(None) => false,
+        Err(e) => return silo_error(&state, e),
+    };
+    if !deleted {
+        return not_found(&key);
+    }
+    if let Err(e) = silos.persist_silo(&name, &master_key).await {
+        return internal_error(&state, e);
+    }
+
+    HttpResponse::Ok().body("Secret deleted successfully")
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+// blocks every read of key without deleting it, e.g. during an incident; reads get 403 until it is enabled again
+#[post("/admin/disable-secret/{key}")]
+async fn disable_secret(req: HttpRequest, path: web::Path<String>, kv: web::Data<KVStore>, state: web::Data<AppState>) -> impl Responder {
+    set_secret_disabled(&req, &path, true, &kv, &state).await
+}
+
+#[post("/admin/enable-secret/{key}")]
+async fn enable_secret(req: HttpRequest, path: web::Path<String>, kv: web::Data<KVStore>, state: web::Data<AppState>) -> impl Responder {
+    set_secret_disabled(&req, &path, false, &kv, &state).await
+}
+
+async fn set_secret_disabled(req: &HttpRequest, key: &str, disabled: bool, kv: &KVStore, state: &AppState) -> HttpResponse {
+    if let Some(response) = admin_denied(req, state) {
+        return response;
+    }
+    if let Some(response) = write_locked(state) {
+        return response;
+    }
+    let master_key = match state.master_key().await {
+        Some(key) => key,
+        None => return sealed(),
+    };
+
+    let changed = if disabled { kv.disable_secret(key).await } else { kv.enable_secret(key).await };
+    if !changed {
+        return not_found(key);
+    }
+    if let Err(e) = kv.persist(&state.secrets_file, &master_key).await {
+        return internal_error(state, e);
+    }
+    HttpResponse::Ok().json(serde_json::json!({ "key": key, "disabled": disabled }))
+}
+
+#[derive(Deserialize)]
+pub struct BulkGrantQuery {
+    #[serde(default = "default_permission")]
+    pub permission: Permission,
+}
+
+fn default_permission() -> Permission {
+    Permission::Read
+}
+
+// who grant changes made with X-Admin-Token are recorded as in the audit log
+const ADMIN_ACTOR: &str = "admin";
+
+fn parse_user_id(user_id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(user_id).map_err(|_| ApiError::InvalidRequest("user_id must be a UUID".to_string()))
+}
+
+// the body is a JSON array of paths; paths the user already holds keep their level
+#[post("/access/{user_id}/bulk-grant")]
+async fn bulk_grant(req: HttpRequest, path: web::Path<String>, query: web::Query<BulkGrantQuery>, data: web::Json<Vec<String>>, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = admin_denied(&req, &state) {
+        return response;
+    }
+    let user_id = match parse_user_id(&path) {
+        Ok(user_id) => user_id,
+        Err(e) => return e.error_response(),
+    };
+
+    let paths: Vec<&str> = data.iter().map(String::as_str).collect();
+    let mut access = state.access.write().await;
+    let granted = access.bulk_grant(user_id, &paths, query.permission, Some(ADMIN_ACTOR));
+    // changes made before a failed audit write are kept, so they are saved either way
+    if let Err(e) = access.save(ACCESS_FILE) {
+        return internal_error(&state, e);
+    }
+    let granted = match granted {
+        Ok(granted) => granted,
+        Err(e) => return internal_error(&state, e),
+    };
+    HttpResponse::Ok().json(serde_json::json!({ "user_id": user_id.to_string(), "granted": granted }))
+}
+
+#[delete("/access/{user_id}/bulk-revoke")]
+async fn bulk_revoke(req: HttpRequest, path: web::Path<String>, data: web::Json<Vec<String>>, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = admin_denied(&req, &state) {
+        return response;
+    }
+    let user_id = match parse_user_id(&path) {
+        Ok(user_id) => user_id,
+        Err(e) => return e.error_response(),
+    };
+
+    let paths: Vec<&str> = data.iter().map(String::as_str).collect();
+    let mut access = state.access.write().await;
+    let revoked = access.bulk_revoke(user_id, &paths, Some(ADMIN_ACTOR));
+    // changes made before a failed audit write are kept, so they are saved either way
+    if let Err(e) = access.save(ACCESS_FILE) {
+        return internal_error(&state, e);
+    }
+    let revoked = match revoked {
+        Ok(revoked) => revoked,
+        Err(e) => return internal_error(&state, e),
+    };
+    HttpResponse::Ok().json(serde_json::json!({ "user_id": user_id.to_string(), "revoked": revoked }))
+}
+
+// reads keep working while writes are locked
+#[post("/admin/lock")]
+async fn lock_writes(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = admin_denied(&req, &state) {
+        return response;
+    }
+
+    state.write_locked.set(true);
+    HttpResponse::Ok().json(serde_json::json!({ "locked": true }))
+}
+
+#[post("/admin/unlock")]
+async fn unlock_writes(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    if let Some(response) = admin_denied(&req, &state) {
+        return response;
+    }
+
+    state.write_locked.set(false);
+    HttpResponse::Ok().json(serde_json::json!({ "locked": false }))
+}
+
+
+
+
+
+
+
+
+//////////////////////////////////////////////////////////////////////
+
+
+
+
+
+
+
+
+// the password check behind /login and --allow-basic-auth, throttled and audited the same way;
+// Err is the response to send instead
+pub async fn check_login(req: &HttpRequest, state: &AppState, username: &str, password: &str) -> Result<(), HttpResponse> {
+    let client = client_ip(req, &state.trusted_proxies);
+    let throttle_keys = LoginThrottle::keys(username, client);
+
+    let users = state.users.read().await;
+    let users = match &*users {
+        Some(users) => users,
+        None => return Err(sealed()),
+    };
+
+    // a locked out attempt is turned away before the deliberately slow bcrypt check
+    if let Err(retry_after) = state.login_throttle.begin(&throttle_keys).await {
+        if let Err(e) = state.audit.record(Some(username), "login", "session", false) {
+            return Err(internal_error(state, e));
+        }
+        return Err(ApiError::TooManyAttempts { retry_after }.error_response());
+    }
+    let authenticated = authenticate_user(users, username, password).await;
+    state.login_throttle.finish(&throttle_keys, authenticated).await;
+
+    if let Err(e) = state.audit.record(Some(username), "login", "session", authenticated) {
+        return Err(internal_error(state, e));
+    }
+    if !authenticated {
+        return Err(ApiError::Unauthorized("invalid username or password".to_string()).error_response());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[post("/login")]
+async fn login(req: HttpRequest, data: web::Json<LoginRequest>, state: web::Data<AppState>) -> impl Responder {
+    if let Err(response) = check_login(&req, &state, &data.username, &data.password).await {
+        return response;
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "token": issue_token(&state.session_key, &data.username, state.session_ttl),
+        "expires_in": state.session_ttl,
+        "user_id": user_id(&data.username).to_string(),
+    }))
+}
@@ -0,0 +1,99 @@
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
+
+// every error the API sends back; the body is {"code": ..., "message": ...} plus
+// the key or limit involved where there is one, so clients can match on code
+#[derive(Debug)]
+pub enum ApiError {
+    KeyNotFound { key: String },
+    AccessDenied(String),
+    DecryptionFailed(String),
+    CapacityExceeded(String),
+    PayloadTooLarge { max_bytes: usize },
+    Unauthorized(String),
+    TooManyAttempts { retry_after: u64 },
+    InvalidRequest(String),
+    InvalidValue(String),
+    Sealed,
+    WriteLocked,
+    HttpsRequired,
+    Internal(String),
+    RateLimited { retry_after: u64 },
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::KeyNotFound { .. } => "E001_KEY_NOT_FOUND",
+            ApiError::AccessDenied(_) => "E002_ACCESS_DENIED",
+            ApiError::DecryptionFailed(_) => "E003_DECRYPTION_FAILED",
+            ApiError::CapacityExceeded(_) => "E004_CAPACITY_EXCEEDED",
+            ApiError::PayloadTooLarge { .. } => "E005_PAYLOAD_TOO_LARGE",
+            ApiError::Unauthorized(_) => "E006_UNAUTHORIZED",
+            ApiError::TooManyAttempts { .. } => "E007_TOO_MANY_ATTEMPTS",
+            ApiError::InvalidRequest(_) => "E008_INVALID_REQUEST",
+            ApiError::InvalidValue(_) => "E009_INVALID_VALUE",
+            ApiError::Sealed => "E010_SEALED",
+            ApiError::WriteLocked => "E011_WRITE_LOCKED",
+            ApiError::HttpsRequired => "E012_HTTPS_REQUIRED",
+            ApiError::Internal(_) => "E013_INTERNAL",
+            ApiError::RateLimited { .. } => "E014_RATE_LIMITED",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::KeyNotFound { .. } => write!(f, "secret not found"),
+            ApiError::PayloadTooLarge { .. } => write!(f, "request body too large"),
+            ApiError::TooManyAttempts { .. } => write!(f, "too many failed login attempts"),
+            ApiError::Sealed => write!(f, "the store is sealed"),
+            ApiError::WriteLocked => write!(f, "store is locked for maintenance"),
+            ApiError::HttpsRequired => write!(f, "HTTPS is required"),
+            ApiError::RateLimited { .. } => write!(f, "too many requests for this user"),
+            ApiError::AccessDenied(message)
+            | ApiError::DecryptionFailed(message)
+            | ApiError::CapacityExceeded(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::InvalidRequest(message)
+            | ApiError::InvalidValue(message)
+            | ApiError::Internal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::KeyNotFound { .. } => StatusCode::NOT_FOUND,
+            ApiError::AccessDenied(_) => StatusCode::FORBIDDEN,
+            ApiError::DecryptionFailed(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::CapacityExceeded(_) | ApiError::Sealed | ApiError::WriteLocked => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::TooManyAttempts { .. } | ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidValue(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::HttpsRequired => StatusCode::MISDIRECTED_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = serde_json::json!({ "code": self.code(), "message": self.to_string() });
+        match self {
+            ApiError::KeyNotFound { key } => body["key"] = key.as_str().into(),
+            ApiError::PayloadTooLarge { max_bytes } => body["max_bytes"] = (*max_bytes).into(),
+            ApiError::TooManyAttempts { retry_after } | ApiError::RateLimited { retry_after } => body["retry_after"] = (*retry_after).into(),
+            _ => {}
+        }
+
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::TooManyAttempts { retry_after } | ApiError::RateLimited { retry_after } = self {
+            response.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(body)
+    }
+}
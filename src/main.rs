@@ -1,26 +1,916 @@
+mod commands;
 mod endpoints;
+mod errors;
+mod middleware;
+mod notify;
+mod session;
 
-use actix_web::{web, App, HttpServer, middleware::Logger};
-use chacha20poly1305::{XChaCha20Poly1305, Key, KeyInit};
+use actix_web::{web, App, HttpServer, http::header::HeaderName, middleware::{from_fn, Logger}};
+use barn::access_control::AccessControl;
+use barn::audit::AuditLog;
+use barn::kv_silo::{get_or_create_key, Algorithm, KVStore, ShareConfig, SiloManager};
+use barn::login_throttle::LoginThrottle;
+use barn::rate_limit::UserRateLimiter;
+use barn::tokens::TokenStore;
+use barn::users::UserStore;
+use clap::{ArgEnum, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use ipnet::IpNet;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sharks::Share;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::sync::{RwLock, Semaphore};
+use zeroize::Zeroizing;
+
+
+const KEY_FILE: &str = "data/encryption_key.bin";
+const USERS_FILE: &str = "data/users.json";
+// the bcrypt cost picked by --auto-tune-bcrypt-cost, read by `barn register`
+const BCRYPT_COST_FILE: &str = "data/bcrypt_cost";
+const SECRETS_FILE: &str = "data/secrets.bin";
+const SEAL_FILE: &str = "data/seal.json";
+// where --save-shares writes the shares of a sealed key, and the share commands read them
+const SHARES_DIR: &str = "data/shares";
+const AUDIT_FILE: &str = "data/audit.log";
+// where --truncate-log-at moves a full audit log
+const AUDIT_OLD_FILE: &str = "data/audit.log.old";
+const TOKENS_FILE: &str = "data/tokens.json";
+const ACCESS_FILE: &str = "data/access.json";
+// where KVStore::new() writes its checkpoints
+const CHECKPOINTS_DIR: &str = "data/checkpoints";
+const SEALED_SECRETS_FILE: &str = "data/secrets.sealed";
+// how long one password hash should take on this machine with --auto-tune-bcrypt-cost
+const BCRYPT_COST_TARGET: std::time::Duration = std::time::Duration::from_millis(250);
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// consecutive self-test failures before each further one is logged as a warning
+const SELF_TEST_ALERT_AFTER: u32 = 3;
 
 struct AppState {
-    encryptor: XChaCha20Poly1305,
+    seal: RwLock<SealState>,
+    request_slots: Semaphore,
+    max_concurrent_requests: usize,
+    user_rate_limit: UserRateLimiter,
+    expose_internal_errors: bool,
+    max_value_size: usize,
+    ip_allowlist: Vec<IpNet>,
+    trusted_proxies: Vec<IpNet>,
+    request_id_header: HeaderName,
+    secrets_file: String,
+    admin_token: Option<String>,
+    audit: Arc<AuditLog>,
+    tokens: TokenStore,
+    // grants managed through /access, checked on secret routes under --require-login and saved
+    // to ACCESS_FILE after every change
+    access: RwLock<AccessControl>,
+    // None until the master key is available to decrypt the users file
+    users: RwLock<Option<UserStore>>,
+    // signs session tokens; generated per start, so restarting logs everyone out
+    session_key: hmac::Key,
+    session_ttl: u64,
+    require_login: bool,
+    require_https: bool,
+    allow_basic_auth: bool,
+    fallback_to_env: bool,
+    // set once loading the secrets file has failed under --fallback-to-env
+    using_env_fallback: AtomicBool,
+    write_locked: WriteLocked,
+    login_throttle: LoginThrottle,
+    // failed runs of the --startup-check-interval self-test, reported by /stats
+    self_test_failures: AtomicU64,
+}
+
+// set by POST /admin/lock to turn away every write until POST /admin/unlock
+#[derive(Clone, Default)]
+struct WriteLocked(Arc<AtomicBool>);
+
+impl WriteLocked {
+    fn is_locked(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, locked: bool) {
+        self.0.store(locked, Ordering::Relaxed);
+    }
+}
+
+impl AppState {
+    // the master key, or None while the store is sealed
+    async fn master_key(&self) -> Option<Zeroizing<Vec<u8>>> {
+        self.seal.read().await.master_key()
+    }
+
+    // with --fallback-to-env an unreadable secrets file is logged and reads fall back to the
+    // environment; writes still go to the (now empty) store. A missing file is just a new store
+    async fn load_secrets(&self, store: &KVStore, master_key: &[u8]) -> std::io::Result<()> {
+        if !std::path::Path::new(&self.secrets_file).exists() {
+            return Ok(());
+        }
+        match store.restore(&self.secrets_file, master_key).await {
+            Err(e) if self.fallback_to_env => {
+                log::warn!("Could not load {} ({}), falling back to environment variables", self.secrets_file, e);
+                self.using_env_fallback.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+enum SealState {
+    // shares submitted to /unseal are kept until there are enough to rebuild the key
+    Sealed { config: SealConfig, submitted: Vec<Share> },
+    Unsealed { master_key: Zeroizing<Vec<u8>> },
+    // --reload-shares-on-access: the key is rebuilt from the share files for each use and dropped
+    // again afterwards, so it is only in memory while a request needs it
+    FromShares { config: SealConfig, shares_dir: PathBuf },
+}
+
+impl SealState {
+    fn master_key(&self) -> Option<Zeroizing<Vec<u8>>> {
+        match self {
+            SealState::Unsealed { master_key } => Some(master_key.clone()),
+            SealState::Sealed { .. } => None,
+            SealState::FromShares { config, shares_dir } => match commands::key_from_share_files(shares_dir, config) {
+                Ok(master_key) => Some(master_key),
+                Err(e) => {
+                    log::warn!("Could not rebuild the master key from {}: {}", shares_dir.display(), e);
+                    None
+                }
+            },
+        }
+    }
+}
+
+// written on first --sealed start; the key itself only ever exists as shares and in memory
+#[derive(Serialize, Deserialize)]
+struct SealConfig {
+    #[serde(flatten)]
+    shares: ShareConfig,
+    // lets /unseal tell a wrong set of shares from the right one
+    fingerprint: String,
+}
+
+#[derive(Parser)]
+#[clap(name = "barn", version, about = "The Barnyard Key-Value Store")]
+struct Cli {
+    /// When the secrets file is unreadable, answer reads for keys the store lacks from BARN_SECRET_<key> environment variables
+    #[clap(long, global = true)]
+    fallback_to_env: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the Barn API server (the default when no command is given)
+    Serve(ServeArgs),
+    /// Generate a new encryption key and show its fingerprint
+    GenKey {
+        /// Write the raw key to this file instead of printing it as hex
+        #[clap(short, long)]
+        output: Option<String>,
+        /// Derive the key from a passphrase with Argon2 instead of the OS RNG
+        #[clap(short, long)]
+        passphrase: bool,
+        /// Key size in bits
+        #[clap(long, default_value = "256", possible_values = ["128", "192", "256"])]
+        bits: usize,
+        /// Benchmark the supported ciphers on this machine and recommend the fastest
+        #[clap(long)]
+        cipher_benchmark: bool,
+    },
+    /// Print the stored ciphertext of a key in a transportable encoding
+    Encode {
+        key: String,
+        #[clap(short, long, arg_enum, default_value = "base64")]
+        format: Encoding,
+    },
+    /// Read an encoded ciphertext from stdin and store it under a key
+    Decode {
+        key: String,
+        #[clap(short, long, arg_enum, default_value = "base64")]
+        format: Encoding,
+    },
+    /// Create a user account that can log in to the server
+    Register {
+        username: String,
+        /// Prompted for when not given
+        #[clap(long)]
+        password: Option<String>,
+    },
+    /// Pick stored keys interactively with fuzzy search and print their names
+    FuzzyFind {
+        /// Keep picking keys until Esc is pressed
+        #[clap(long)]
+        multi: bool,
+    },
+    /// Check that a key can decrypt an encrypted store file
+    VerifyKey {
+        #[clap(long)]
+        key_file: String,
+        #[clap(long)]
+        store_file: String,
+    },
+    /// Strip leading and trailing whitespace and NUL bytes from stored secret values (stop the server first)
+    Trim {
+        /// Only show which secrets would change
+        #[clap(long)]
+        dry_run: bool,
+        /// Comma-separated keys to trim instead of every secret
+        #[clap(long, value_delimiter = ',')]
+        keys: Option<Vec<String>>,
+    },
+    /// Show a secret's metadata, versions and nonce without decrypting its value
+    Describe {
+        key: String,
+    },
+    /// Add a tag to a secret in the secrets file without touching its value (stop the server first)
+    SetTag {
+        key: String,
+        tag: String,
+    },
+    /// Remove a tag from a secret in the secrets file without touching its value (stop the server first)
+    RemoveTag {
+        key: String,
+        tag: String,
+    },
+    /// Write secrets as KEY=VALUE lines to a .env file readable only by its owner
+    ExportEnvFile {
+        output: String,
+        /// Secrets to export (all of them when none are given)
+        keys: Vec<String>,
+    },
+    /// Render a Handlebars template with every secret as a variable to a file readable only by its owner
+    Template {
+        input: String,
+        output: String,
+        /// Fail on variables that are not secrets instead of rendering them empty
+        #[clap(long)]
+        strict: bool,
+    },
+    /// Run a command with a secret on its stdin, e.g. `barn pipe --key db-password -- psql -U admin`
+    Pipe {
+        /// Secret to decrypt and write to the command's stdin
+        #[clap(long)]
+        key: String,
+        /// The command and its arguments, after --
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Check that a secret's SHA-256 matches, printing PASS or FAIL but never the value
+    Assert {
+        key: String,
+        /// Hex SHA-256 digest the decrypted value should have
+        expected_sha256: String,
+    },
+    /// Create a token that lets GET /secret/{key}?token=... read one secret without logging in
+    GenerateToken {
+        key: String,
+        /// Seconds until the token stops working
+        #[clap(long, default_value = "3600")]
+        ttl_seconds: u64,
+        /// Reads the token allows before it is used up
+        #[clap(long, default_value = "1")]
+        max_uses: u32,
+    },
+    /// Create a self-signed TLS certificate and its private key as PEM files
+    GenerateCert {
+        common_name: String,
+        /// Days the certificate stays valid
+        #[clap(long, default_value = "365")]
+        days: u64,
+        #[clap(long, default_value = "cert.pem")]
+        output_cert: String,
+        #[clap(long, default_value = "key.pem")]
+        output_key: String,
+        #[clap(long, arg_enum, default_value = "ed25519")]
+        algorithm: CertAlgorithm,
+        /// Also keep the private key in the secrets store under this name
+        #[clap(long)]
+        store_key: Option<String>,
+    },
+    /// Download a secret over HTTPS, e.g. a certificate from a CA, and store the body encrypted
+    Fetch {
+        url: String,
+        store_key: String,
+        /// Seconds until the stored secret expires; the response's Cache-Control max-age when unset
+        #[clap(long)]
+        ttl: Option<u64>,
+        /// Skip TLS certificate validation, so anyone on the network path can supply the secret
+        #[clap(long)]
+        insecure: bool,
+    },
+    /// Show secrets that are past their expiry but have not been purged yet
+    ListExpired {
+        /// Also show the encrypted size of each secret
+        #[clap(short, long)]
+        verbose: bool,
+        /// Purge the listed secrets from the secrets file
+        #[clap(long)]
+        delete: bool,
+    },
+    /// List or delete every file barn has written under the data directory, keys included
+    WipeCache {
+        /// Directory the server keeps its files in
+        #[clap(long, default_value = "data")]
+        data_dir: String,
+        /// Only list the files and their sizes
+        #[clap(long)]
+        dry_run: bool,
+        /// Required to actually delete, since this also removes the master key and secrets
+        #[clap(long)]
+        confirm: bool,
+    },
+    /// List the key share files in {data-dir}/shares and whether they reach the unseal threshold
+    ListShares {
+        /// Directory the server keeps its files in
+        #[clap(long, default_value = "data")]
+        data_dir: String,
+    },
+    /// List or delete key share files in {data-dir}/shares older than --max-age-days
+    CleanShares {
+        /// Directory the server keeps its files in
+        #[clap(long, default_value = "data")]
+        data_dir: String,
+        /// Files last modified longer ago than this are removed
+        #[clap(long)]
+        max_age_days: u64,
+        /// Only list the files that would be removed
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Send each key share file in {data-dir}/shares to one share holder listed in a TOML notifier config
+    ExportShares {
+        /// TOML file with an [smtp] table and one [[notifiers]] entry (type = "email" or "sms") per share
+        #[clap(long)]
+        notifier_config: String,
+        /// Directory the server keeps its files in
+        #[clap(long, default_value = "data")]
+        data_dir: String,
+    },
+    /// Reject every write to a running server until it is unlocked (needs its --admin-token)
+    Lock(AdminArgs),
+    /// Allow writes again after `barn lock`
+    Unlock(AdminArgs),
+    /// Replace the master key and re-encrypt the secrets and users files under it (stop the server first)
+    RotateKey {
+        /// The --pid-file the server was started with; rotation is refused while that server runs
+        #[clap(long)]
+        pid_file: Option<String>,
+        /// Rotate even if some stored values can't be read with the current key; they stay unreadable
+        #[clap(long)]
+        force: bool,
+    },
+    /// Inspect the tamper-evident audit log
+    Audit {
+        #[clap(subcommand)]
+        command: AuditCommand,
+    },
+    /// Watch a secrets file and print the keys that change each time it is written, never the values
+    Tail {
+        #[clap(default_value = SECRETS_FILE)]
+        file: String,
+    },
+    /// Save the secrets file as it is now to data/checkpoints/{label}_{timestamp}.bin
+    Checkpoint {
+        label: String,
+    },
+    /// Replace the secrets file with a checkpoint written by `barn checkpoint` (stop the server first)
+    RestoreCheckpoint {
+        path: String,
+    },
+    /// Rebuild a secrets file from the writes and deletes in an audit log written with --log-sensitive-ops
+    Restore {
+        audit_log: String,
+        /// Where the rebuilt secrets file is written; it must not exist yet
+        output: String,
+    },
+    /// Copy every secret in a silo, history included, to a new silo
+    Clone {
+        source_context: String,
+        target_context: String,
+        /// Re-encrypt the clone under a fresh data key; refused, silos have no key of their own
+        #[clap(long)]
+        re_encrypt: bool,
+        /// Decrypt and re-encrypt each value under fresh nonces instead of copying the ciphertext; the
+        /// clone stays under the same master key
+        #[clap(long)]
+        re_nonce: bool,
+        /// The --pid-file the server was started with; cloning is refused while that server runs
+        #[clap(long)]
+        pid_file: Option<String>,
+    },
+    /// Write every secret and its history to a passphrase-encrypted bundle (stop the server first)
+    Export {
+        output: String,
+    },
+    /// Merge a bundle written by export into the secrets file (stop the server first)
+    Import {
+        input: String,
+        /// Replace secrets that already exist instead of skipping them
+        #[clap(long)]
+        overwrite: bool,
+        /// What the input is: a bundle from `barn export`, or the output of `vault kv get -format=json`
+        #[clap(long, arg_enum, default_value = "bundle")]
+        format: ImportFormat,
+        /// Vault path the fields are stored under as {path}/{field}; the input's file name without
+        /// its extension when unset
+        #[clap(long)]
+        vault_path: Option<String>,
+    },
+    /// Encrypt stdin with the master key, writing the nonce and ciphertext as two hex lines
+    EncryptStdin,
+    /// Decrypt the output of encrypt-stdin from stdin, writing the raw plaintext
+    DecryptStdin,
+    /// Encrypt the secrets file under a new data key wrapped by AWS KMS, writing data/secrets.sealed
+    #[cfg(feature = "kms")]
+    SealVault {
+        /// KMS key ID, ARN or alias used to wrap the data key
+        kms_key_id: String,
+    },
+    /// Restore the secrets file from data/secrets.sealed by unwrapping its data key with AWS KMS
+    #[cfg(feature = "kms")]
+    UnsealVault {
+        /// Fail unless the data key was wrapped with this KMS key
+        #[clap(long)]
+        kms_key_id: Option<String>,
+        /// Replace an existing secrets file
+        #[clap(long)]
+        force: bool,
+    },
+    /// Print the settings serve would run with as TOML, noting where each value came from
+    PrintConfig(ServeArgs),
+    /// Check the key, encryption, store persistence and key shares before deploying; exits 1 if any check fails
+    SelfTest,
+    /// Encrypt and decrypt a string with the current key to check that it round-trips
+    TestEncryption {
+        plaintext: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommand {
+    /// Walk the hash chain and report the first entry that was changed or removed
+    Verify {
+        #[clap(long, default_value = AUDIT_FILE)]
+        file: String,
+    },
+    /// Replay the grants and revokes recorded in the log, oldest first
+    Grants {
+        #[clap(long, default_value = AUDIT_FILE)]
+        file: String,
+    },
+}
+
+#[derive(Parser)]
+struct ServeArgs {
+    /// Address to listen on, e.g. 0.0.0.0:8000 inside a container
+    #[clap(short, long, default_value = "127.0.0.1:8000")]
+    address: String,
+    /// Reject requests with 503 once this many are being handled at the same time
+    #[clap(long)]
+    max_concurrent_requests: Option<usize>,
+    /// Requests a minute each logged-in user may make, whichever addresses they come from
+    #[clap(long, default_value = "200")]
+    user_rate_limit: u32,
+    /// Return the underlying error message in 500 responses (development only)
+    #[clap(long)]
+    expose_internal_errors: bool,
+    /// Largest request body accepted, in bytes; bigger requests get 413
+    #[clap(long, default_value = "1048576")]
+    max_value_size: usize,
+    /// Only accept clients from these comma-separated CIDR ranges (all clients when unset)
+    #[clap(long, value_delimiter = ',')]
+    ip_allowlist: Vec<IpNet>,
+    /// Proxies, as comma-separated CIDR ranges, whose Forwarded / X-Forwarded-For header names the
+    /// client; the header is ignored from every other peer
+    #[clap(long, value_delimiter = ',')]
+    trusted_proxies: Vec<IpNet>,
+    /// Header carrying the correlation ID that is logged and echoed back (generated when absent)
+    #[clap(long, default_value = "X-Request-Id")]
+    request_id_header: HeaderName,
+    /// Token expected in X-Admin-Token for /admin endpoints (they are disabled when unset)
+    #[clap(long)]
+    admin_token: Option<String>,
+    /// Log the client IP of each request (0.0.0.0 is logged in its place otherwise)
+    #[clap(long)]
+    log_ip_addresses: bool,
+    /// Longest secret key name accepted, in bytes; longer keys get 400
+    #[clap(long, default_value = "256")]
+    max_key_length: usize,
+    /// Versions kept per secret, the current one included; older ones are dropped
+    #[clap(long, default_value = "5")]
+    max_versions: usize,
+    /// Reject /secret, /store and /load requests without a session token from POST /login
+    #[clap(long)]
+    require_login: bool,
+    /// Redirect plain HTTP GET requests to HTTPS and reject other methods with 421
+    /// (the scheme is read from Forwarded / X-Forwarded-Proto behind a TLS proxy)
+    #[clap(long)]
+    require_https: bool,
+    /// Also accept `Authorization: Basic` on every request, answered with a 5-minute session token
+    /// in X-Session-Token; the password then travels with each such request, so only use it over TLS
+    #[clap(long)]
+    allow_basic_auth: bool,
+    /// Seconds a session token from POST /login stays valid
+    #[clap(long, default_value = "3600")]
+    session_ttl: u64,
+    /// Failed logins for one username or client address before it is locked out
+    #[clap(long, default_value = "5")]
+    login_max_failures: u32,
+    /// Seconds within which those failures have to happen
+    #[clap(long, default_value = "300")]
+    login_window: u64,
+    /// Longest lockout in seconds; each lockout doubles the last, starting at 30
+    #[clap(long, default_value = "3600")]
+    login_max_backoff: u64,
+    /// Run the store round-trip self-test every this many seconds, starting at startup
+    #[clap(long)]
+    startup_check_interval: Option<u64>,
+    /// Keep the master key only as Shamir shares and start sealed until they are POSTed to /unseal
+    #[clap(long)]
+    sealed: bool,
+    /// Number of shares to split the key into on the first --sealed start
+    #[clap(long, default_value = "5")]
+    key_shares: u8,
+    /// Number of shares needed to unseal, set on the first --sealed start
+    #[clap(long, default_value = "3")]
+    key_threshold: u8,
+    /// Also save the shares to data/shares on the first --sealed start, one file each; anyone who
+    /// can read a threshold of them has the key, so deliver them with export-shares and delete them
+    #[clap(long)]
+    save_shares: bool,
+    /// Directory the share files are saved to with --save-shares and read from with
+    /// --reload-shares-on-access
+    #[clap(long, default_value = SHARES_DIR)]
+    shares_dir: String,
+    /// Instead of keeping the key in memory once unsealed, rebuild it from the share files in
+    /// --shares-dir for every access and drop it afterwards (needs --sealed; slower)
+    #[clap(long)]
+    reload_shares_on_access: bool,
+    /// Cipher the secrets file is written with; files in either cipher are read
+    #[clap(long, arg_enum, default_value = "xchacha20-poly1305")]
+    cipher: FileCipher,
+    /// Write the server's PID to this file, removed again on shutdown
+    #[clap(long)]
+    pid_file: Option<String>,
+    /// Move the audit log to data/audit.log.old once it reaches this many bytes and start a new one
+    #[clap(long)]
+    truncate_log_at: Option<u64>,
+    /// Write key names to the audit log as-is instead of hashed; values are only ever logged as a SHA-256
+    #[clap(long)]
+    log_sensitive_ops: bool,
+    /// Do not write an audit log at all
+    #[clap(long, conflicts_with_all = &["log-sensitive-ops", "truncate-log-at"])]
+    no_audit: bool,
+    /// Raise the bcrypt cost at startup until one hash takes 250ms and keep it for new accounts
+    #[clap(long)]
+    auto_tune_bcrypt_cost: bool,
+}
+
+#[derive(Parser)]
+struct AdminArgs {
+    /// Base URL of the running server
+    #[clap(long, default_value = "http://127.0.0.1:8000")]
+    server: String,
+    /// The --admin-token the server was started with
+    #[clap(long)]
+    admin_token: String,
+}
+
+#[derive(ArgEnum, Clone, Copy)]
+pub enum ImportFormat {
+    Bundle,
+    VaultJson,
+}
+
+#[derive(ArgEnum, Clone, Copy)]
+pub enum Encoding {
+    Base64,
+    Base64Url,
+    Hex,
+    Pem,
+}
+
+#[derive(ArgEnum, Clone, Copy)]
+pub enum FileCipher {
+    #[clap(name = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+    #[clap(name = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+impl From<FileCipher> for Algorithm {
+    fn from(cipher: FileCipher) -> Self {
+        match cipher {
+            FileCipher::XChaCha20Poly1305 => Algorithm::XChaCha20Poly1305,
+            FileCipher::Aes256Gcm => Algorithm::Aes256Gcm,
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy)]
+pub enum CertAlgorithm {
+    Ed25519,
+    EcdsaP256,
 }
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
+async fn main() {
+    // the matches are kept so print-config can tell which values were passed explicitly
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // no subcommand means `barn serve` with its defaults
+    let command = cli.command.unwrap_or_else(|| Command::Serve(ServeArgs::parse_from(["serve"])));
+
+    if let Err(e) = run(command, cli.fallback_to_env, &matches).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(command: Command, fallback_to_env: bool, matches: &ArgMatches) -> std::io::Result<()> {
+    match command {
+        Command::Serve(args) => serve(args, fallback_to_env).await,
+        Command::GenKey { output, passphrase, bits, cipher_benchmark } => {
+            commands::gen_key(output.as_deref(), passphrase, bits)?;
+            if cipher_benchmark {
+                commands::cipher_benchmark();
+            }
+            Ok(())
+        }
+        Command::Encode { key, format } => commands::encode(&key, format),
+        Command::Decode { key, format } => commands::decode(&key, format),
+        Command::Register { username, password } => commands::register(&username, password).await,
+        Command::FuzzyFind { multi } => commands::fuzzy_find(multi),
+        Command::VerifyKey { key_file, store_file } => commands::verify_key(&key_file, &store_file).await,
+        Command::Trim { dry_run, keys } => commands::trim(dry_run, keys).await,
+        Command::Describe { key } => commands::describe(&key).await,
+        Command::SetTag { key, tag } => commands::update_tag(&key, &tag, true).await,
+        Command::RemoveTag { key, tag } => commands::update_tag(&key, &tag, false).await,
+        Command::ExportEnvFile { output, keys } => commands::export_env_file(&output, &keys).await,
+        Command::Template { input, output, strict } => commands::template(&input, &output, strict).await,
+        Command::Pipe { key, command } => commands::pipe(&key, &command).await,
+        Command::Assert { key, expected_sha256 } => commands::assert(&key, &expected_sha256).await,
+        Command::GenerateToken { key, ttl_seconds, max_uses } => commands::generate_token(&key, ttl_seconds, max_uses).await,
+        Command::GenerateCert { common_name, days, output_cert, output_key, algorithm, store_key } => {
+            commands::generate_cert(&common_name, days, &output_cert, &output_key, algorithm, store_key.as_deref()).await
+        }
+        Command::Fetch { url, store_key, ttl, insecure } => commands::fetch(&url, &store_key, ttl, insecure).await,
+        Command::ListExpired { verbose, delete } => commands::list_expired(verbose, delete).await,
+        Command::WipeCache { data_dir, dry_run, confirm } => commands::wipe_cache(&data_dir, dry_run, confirm),
+        Command::ListShares { data_dir } => commands::list_shares(&data_dir),
+        Command::CleanShares { data_dir, max_age_days, dry_run } => commands::clean_shares(&data_dir, max_age_days, dry_run),
+        Command::ExportShares { notifier_config, data_dir } => commands::export_shares(&notifier_config, &data_dir),
+        Command::Lock(args) => commands::set_write_lock(&args.server, &args.admin_token, true).await,
+        Command::Unlock(args) => commands::set_write_lock(&args.server, &args.admin_token, false).await,
+        Command::RotateKey { pid_file, force } => commands::rotate_key(pid_file.as_deref(), force).await,
+        Command::Audit { command: AuditCommand::Verify { file } } => commands::audit_verify(&file),
+        Command::Audit { command: AuditCommand::Grants { file } } => commands::audit_grants(&file),
+        Command::Tail { file } => commands::tail(&file).await,
+        Command::Checkpoint { label } => commands::checkpoint(&label).await,
+        Command::RestoreCheckpoint { path } => commands::restore_checkpoint(&path).await,
+        Command::Restore { audit_log, output } => commands::restore(&audit_log, &output).await,
+        Command::Clone { source_context, target_context, re_encrypt, re_nonce, pid_file } => {
+            commands::clone_silo(&source_context, &target_context, re_encrypt, re_nonce, pid_file.as_deref()).await
+        }
+        Command::Export { output } => commands::export(&output).await,
+        Command::Import { input, overwrite, format: ImportFormat::Bundle, .. } => commands::import(&input, overwrite).await,
+        Command::Import { input, overwrite, format: ImportFormat::VaultJson, vault_path } => {
+            commands::import_vault_json(&input, vault_path.as_deref(), overwrite).await
+        }
+        Command::EncryptStdin => commands::encrypt_stdin(),
+        Command::DecryptStdin => commands::decrypt_stdin(),
+        #[cfg(feature = "kms")]
+        Command::SealVault { kms_key_id } => commands::seal_vault(&kms_key_id).await,
+        #[cfg(feature = "kms")]
+        Command::UnsealVault { kms_key_id, force } => commands::unseal_vault(kms_key_id.as_deref(), force).await,
+        Command::PrintConfig(_) => commands::print_config(matches.subcommand_matches("print-config").unwrap_or(matches)),
+        Command::SelfTest => commands::self_test().await,
+        Command::TestEncryption { plaintext } => commands::test_encryption(&plaintext),
+    }
+}
+
+// removes the PID file when dropped, which covers a failed bind as well as the graceful
+// shutdown actix runs on SIGINT and SIGTERM
+struct PidFile(String);
+
+// how long an empty PID file is given to get its PID before it counts as left behind
+const PID_FILE_WRITE_GRACE_MS: u64 = 100;
+
+impl PidFile {
+    // create_new makes starting two servers at once safe: only one of them creates the file. A file
+    // naming a process that is gone was left behind by a crash and is replaced
+    fn create(path: &str) -> std::io::Result<PidFile> {
+        let pid = std::process::id();
+        loop {
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    file.write_all(format!("{}\n", pid).as_bytes())?;
+                    return Ok(PidFile(path.to_string()));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+
+            // a server that has only just created the file may not have written its PID yet
+            let recorded = match recorded_pid(path) {
+                None => {
+                    std::thread::sleep(std::time::Duration::from_millis(PID_FILE_WRITE_GRACE_MS));
+                    recorded_pid(path)
+                }
+                recorded => recorded,
+            };
+            if let Some(pid) = recorded.filter(|&pid| process_running(pid)) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("Another Barn server is already running (PID {})", pid),
+                ));
+            }
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// another server that took the file over after us owns it now
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if recorded_pid(&self.0) == Some(std::process::id()) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+}
+
+fn recorded_pid(path: &str) -> Option<u32> {
+    std::fs::read_to_string(path).ok().and_then(|contents| contents.trim().parse::<u32>().ok())
+}
+
+// the PID in path, if that process is still running
+fn running_pid(path: &str) -> Option<u32> {
+    recorded_pid(path).filter(|&pid| process_running(pid))
+}
+
+// kill -0 sends nothing, it only reports whether the process exists
+fn process_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        return std::path::Path::new(&format!("/proc/{}", pid)).exists();
+    }
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+async fn serve(args: ServeArgs, fallback_to_env: bool) -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "actix_web=info,barn=info");
     env_logger::init();
 
-    let mut key_bytes = [0u8; 32];
-    OsRng.fill_bytes(&mut key_bytes);
-    let key = Key::from_slice(&key_bytes);
+    let _pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
+
+    if args.reload_shares_on_access && !args.sealed {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "--reload-shares-on-access needs --sealed, the key has no shares otherwise"));
+    }
+    let seal = if args.sealed {
+        let shares_dir = PathBuf::from(&args.shares_dir);
+        let config = commands::load_or_init_seal(SEAL_FILE, args.key_threshold, args.key_shares, args.save_shares, &shares_dir)?;
+        if args.reload_shares_on_access {
+            // checked once up front, so a start with unusable share files fails instead of serving 503s
+            commands::key_from_share_files(&shares_dir, &config)?;
+            SealState::FromShares { config, shares_dir }
+        } else {
+            SealState::Sealed { config, submitted: Vec::new() }
+        }
+    } else {
+        SealState::Unsealed { master_key: get_or_create_key(KEY_FILE)? }
+    };
+
+    // data/ exists by now, created along with the key or the seal
+    if args.auto_tune_bcrypt_cost {
+        let cost = barn::users::tune_bcrypt_cost(BCRYPT_COST_TARGET)?;
+        barn::users::save_bcrypt_cost(BCRYPT_COST_FILE, cost)?;
+        log::info!("Selected bcrypt cost {}", cost);
+    }
+
+    let max_concurrent_requests = args.max_concurrent_requests.unwrap_or(Semaphore::MAX_PERMITS);
 
-    let encryptor = XChaCha20Poly1305::new(&key);
+    let users = match seal.master_key() {
+        Some(master_key) => Some(UserStore::load(USERS_FILE, &master_key).await?),
+        None => None,
+    };
 
-    let state = web::Data::new(AppState { encryptor });
+    let mut session_secret = [0u8; 32];
+    OsRng.fill_bytes(&mut session_secret);
+
+    let audit = Arc::new(if args.no_audit {
+        AuditLog::disabled()
+    } else {
+        AuditLog::open(AUDIT_FILE)?.with_truncate_at(args.truncate_log_at).with_sensitive_ops(args.log_sensitive_ops)
+    });
+    let state = web::Data::new(AppState {
+        seal: RwLock::new(seal),
+        request_slots: Semaphore::new(max_concurrent_requests),
+        max_concurrent_requests,
+        user_rate_limit: UserRateLimiter::new(args.user_rate_limit),
+        expose_internal_errors: args.expose_internal_errors,
+        max_value_size: args.max_value_size,
+        ip_allowlist: args.ip_allowlist,
+        trusted_proxies: args.trusted_proxies.clone(),
+        request_id_header: args.request_id_header.clone(),
+        secrets_file: SECRETS_FILE.to_string(),
+        admin_token: args.admin_token.clone(),
+        audit: audit.clone(),
+        tokens: TokenStore::new(TOKENS_FILE),
+        access: RwLock::new(AccessControl::load(ACCESS_FILE)?.with_audit_log(audit)),
+        users: RwLock::new(users),
+        session_key: hmac::Key::new(hmac::HMAC_SHA256, &session_secret),
+        session_ttl: args.session_ttl,
+        require_login: args.require_login,
+        require_https: args.require_https,
+        allow_basic_auth: args.allow_basic_auth,
+        fallback_to_env,
+        using_env_fallback: AtomicBool::new(false),
+        write_locked: WriteLocked::default(),
+        login_throttle: LoginThrottle::new(args.login_max_failures, args.login_window, args.login_max_backoff),
+        self_test_failures: AtomicU64::new(0),
+    });
+
+    // silos are loaded from disk on first use, so a sealed start needs nothing extra
+    let silos = web::Data::new(SiloManager::new("data").with_max_key_length(args.max_key_length));
+
+    // a sealed store is restored by /unseal once the key is back
+    let store = web::Data::new(
+        KVStore::new()
+            .with_max_key_length(args.max_key_length)
+            .with_max_versions(args.max_versions)
+            .with_algorithm(args.cipher.into()),
+    );
+    if let Some(key_bytes) = state.master_key().await {
+        state.load_secrets(&store, &key_bytes).await?;
+    }
+
+    // expired secrets nobody reads would otherwise stay in the secrets file for good
+    let sweeper = (store.clone(), state.clone());
+    tokio::spawn(async move {
+        let (store, state) = sweeper;
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if store.sweep_expired().await == 0 {
+                continue;
+            }
+            if let Some(master_key) = state.master_key().await {
+                if let Err(e) = store.persist(&state.secrets_file, &master_key).await {
+                    log::warn!("Failed to save secrets after removing expired ones: {}", e);
+                }
+            }
+        }
+    });
+
+    if let Some(seconds) = args.startup_check_interval {
+        let checker = state.clone();
+        let algorithm = args.cipher.into();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(seconds.max(1)));
+            let mut consecutive_failures = 0;
+            loop {
+                interval.tick().await;
+                let master_key = match checker.master_key().await {
+                    Some(key) => key,
+                    None => {
+                        log::info!("self_test status=skipped reason=sealed");
+                        continue;
+                    }
+                };
+
+                let started = std::time::Instant::now();
+                match commands::kv_round_trip("data", &master_key, algorithm).await {
+                    Ok(()) => {
+                        consecutive_failures = 0;
+                        log::info!("self_test status=ok duration_ms={}", started.elapsed().as_millis());
+                    }
+                    Err(e) => {
+                        consecutive_failures += 1;
+                        let total = checker.self_test_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                        log::info!("self_test status=failed duration_ms={} failures_total={} error={:?}", started.elapsed().as_millis(), total, e.to_string());
+                        if consecutive_failures >= SELF_TEST_ALERT_AFTER {
+                            log::warn!("Self-test has failed {} times in a row: {}", consecutive_failures, e);
+                        }
+                    }
+                }
+            }
+        });
+    }
 
     let logo = r#"
 ===========================================================
@@ -37,17 +927,99 @@ async fn main() -> std::io::Result<()> {
 
     println!("{}", logo);
     println!("Welcome to the Barnyard Key-Value Store.");
-    println!("Starting Barn API server on http://127.0.0.1:8000");
-    HttpServer::new(move || {
+    match (&*state.seal.read().await, &*state.users.read().await) {
+        (SealState::Sealed { config, .. }, _) => {
+            println!("The store is sealed: POST {} key shares to /unseal", config.shares.threshold);
+        }
+        (SealState::FromShares { shares_dir, .. }, _) => {
+            println!("The master key is rebuilt from the shares in {} on every access", shares_dir.display());
+        }
+        (SealState::Unsealed { .. }, Some(users)) => println!("Loaded {} registered users", users.len().await),
+        (SealState::Unsealed { .. }, None) => {}
+    }
+    #[cfg(not(debug_assertions))]
+    if args.expose_internal_errors {
+        println!("WARNING: --expose-internal-errors is meant for development and leaks error details in a release build");
+    }
+    if args.allow_basic_auth {
+        println!("WARNING: --allow-basic-auth accepts a username and password on every request; only use it over HTTPS");
+    }
+    if args.no_audit {
+        println!("WARNING: --no-audit is set; no request is written to the audit log");
+    }
+    let max_value_size = args.max_value_size;
+    let log_ip_addresses = args.log_ip_addresses;
+    let trusted_proxies = args.trusted_proxies.clone();
+    let log_format = format!(
+        "client_ip=%{{client_ip}}xi \"%r\" %s %b \"%{{Referer}}i\" \"%{{User-Agent}}i\" %T request_id=%{{{}}}o",
+        args.request_id_header
+    );
+    let server = HttpServer::new(move || {
+        let trusted_proxies = trusted_proxies.clone();
         App::new()
-            .wrap(Logger::default())
+            .app_data(web::JsonConfig::default()
+                .limit(max_value_size)
+                .error_handler(move |err, _req| middleware::json_error(err, max_value_size)))
+            .app_data(web::PayloadConfig::new(max_value_size))
+            .app_data(web::QueryConfig::default().error_handler(|err, _req| errors::ApiError::InvalidRequest(err.to_string()).into()))
+            // inside basic_auth, so Basic credentials count against the same user as their tokens
+            .wrap(from_fn(middleware::limit_user_rate))
+            .wrap(from_fn(middleware::basic_auth))
+            .wrap(from_fn(middleware::reject_oversized_bodies))
+            .wrap(from_fn(middleware::limit_concurrency))
+            .wrap(from_fn(middleware::check_ip_allowlist))
+            .wrap(from_fn(middleware::require_https))
+            .wrap(from_fn(middleware::propagate_request_id))
+            .wrap(Logger::new(&log_format).custom_request_replace("client_ip", move |req| {
+                // same source as --ip-allowlist, so X-Forwarded-For only counts from --trusted-proxies
+                match middleware::client_ip(req.request(), &trusted_proxies) {
+                    Some(ip) if log_ip_addresses => ip.to_string(),
+                    _ => "0.0.0.0".to_string(),
+                }
+            }))
             .app_data(state.clone())
+            .app_data(store.clone())
+            .app_data(silos.clone())
             .service(endpoints::store)
             .service(endpoints::load)
+            .service(endpoints::store_batch)
+            .service(endpoints::load_batch)
+            .service(endpoints::put_secret)
+            .service(endpoints::get_secret)
+            .service(endpoints::delete_secret)
+            .service(endpoints::batch_delete_secrets)
+            .service(endpoints::secret_value)
+            .service(endpoints::secret_exists)
+            .service(endpoints::add_secret_tag)
+            .service(endpoints::remove_secret_tag)
+            .service(endpoints::sign_secret)
+            .service(endpoints::verify_secret_signature)
             .service(endpoints::generate_key)
-            //.service(endpoints::login)
+            .service(endpoints::stats)
+            .service(endpoints::tag_stats)
+            .service(endpoints::version)
+            .service(endpoints::store_info)
+            .service(endpoints::invalidate_cache)
+            .service(endpoints::disable_secret)
+            .service(endpoints::enable_secret)
+            .service(endpoints::bulk_grant)
+            .service(endpoints::bulk_revoke)
+            .service(endpoints::lock_writes)
+            .service(endpoints::unlock_writes)
+            .service(endpoints::unseal)
+            .service(endpoints::seal_status)
+            .service(endpoints::list_silos)
+            .service(endpoints::put_silo_secret)
+            .service(endpoints::get_silo_secret)
+            .service(endpoints::delete_silo_secret)
+            .service(endpoints::login)
     })
-    .bind("127.0.0.1:8000")?
-    .run()
-    .await
+    .bind(&args.address)
+    .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to bind to {}: {}", args.address, e)))?;
+
+    for addr in server.addrs() {
+        println!("Starting Barn API server on http://{}", addr);
+    }
+
+    server.run().await
 }
@@ -0,0 +1,235 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, LOCATION};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpRequest, HttpResponse, ResponseError};
+use barn::access_control::user_id;
+use ipnet::IpNet;
+use log::warn;
+use sodiumoxide::base64;
+use std::net::{IpAddr, SocketAddr};
+use uuid::Uuid;
+
+use crate::endpoints::check_login;
+use crate::errors::ApiError;
+use crate::session::{issue_token, verify_token};
+use crate::AppState;
+
+// how long the session token handed out for a Basic-authenticated request stays valid
+const BASIC_AUTH_TOKEN_TTL: u64 = 300;
+// the response header that token is sent back in
+const SESSION_TOKEN_HEADER: &str = "x-session-token";
+
+// holds a request slot for as long as the handler runs, failing fast with 503 when none are free
+pub async fn limit_concurrency(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned().expect("AppState is registered");
+
+    let _permit = match state.request_slots.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            let response = ApiError::CapacityExceeded("too many concurrent requests".to_string()).error_response();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    };
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// a token bucket per logged-in user, so one account can't get around the per-client limits by
+// spreading its requests over several addresses; anonymous requests and tokens that don't verify
+// are left for the Session extractor
+pub async fn limit_user_rate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned().expect("AppState is registered");
+
+    let username = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| verify_token(&state.session_key, token).ok());
+    if let Some(username) = username {
+        if let Err(retry_after) = state.user_rate_limit.check(user_id(&username)) {
+            let response = ApiError::RateLimited { retry_after }.error_response();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+fn payload_too_large(max_bytes: usize) -> HttpResponse {
+    ApiError::PayloadTooLarge { max_bytes }.error_response()
+}
+
+// rejects bodies up front when the client declares a Content-Length over --max-value-size;
+// chunked bodies are still caught by the JsonConfig and PayloadConfig limits
+pub async fn reject_oversized_bodies(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned().expect("AppState is registered");
+
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+
+    if declared.is_some_and(|length| length > state.max_value_size) {
+        let response = payload_too_large(state.max_value_size);
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+pub fn json_error(err: JsonPayloadError, max_bytes: usize) -> Error {
+    match err {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            InternalError::from_response(err, payload_too_large(max_bytes)).into()
+        }
+        err => ApiError::InvalidRequest(err.to_string()).into(),
+    }
+}
+
+pub async fn check_ip_allowlist(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned().expect("AppState is registered");
+
+    if !state.ip_allowlist.is_empty() {
+        let ip = client_ip(req.request(), &state.trusted_proxies);
+
+        if !ip.is_some_and(|ip| state.ip_allowlist.iter().any(|net| net.contains(&ip))) {
+            warn!("Rejected client {} for {}", ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string()), req.path());
+            let response = ApiError::AccessDenied("client address not allowed".to_string()).error_response();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// barn does not terminate TLS itself, so the scheme comes from the proxy in front of it
+// when that proxy is one of --trusted-proxies
+pub async fn require_https(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned().expect("AppState is registered");
+
+    if state.require_https && request_scheme(req.request(), &state.trusted_proxies) != "https" {
+        let info = req.connection_info().clone();
+        let host = info.host().to_owned();
+        let client = client_ip(req.request(), &state.trusted_proxies).map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+        warn!("Plain HTTP {} {} from {} while HTTPS is required", req.method(), req.uri(), client);
+
+        let response = if req.method() == Method::GET {
+            let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+            HttpResponse::MovedPermanently()
+                .insert_header((LOCATION, format!("https://{}{}", host, path)))
+                .finish()
+        } else {
+            ApiError::HttpsRequired.error_response()
+        };
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// with --allow-basic-auth, `Authorization: Basic` is checked like a POST /login and swapped for a
+// short-lived Bearer token before the handler sees it; the token goes back in X-Session-Token so
+// the client can send it instead of the password for the next few minutes
+pub async fn basic_auth(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned().expect("AppState is registered");
+
+    let encoded = match req.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok()).and_then(|value| value.strip_prefix("Basic ")) {
+        Some(encoded) if state.allow_basic_auth => encoded.to_string(),
+        _ => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+    let credentials = base64::decode(&encoded, base64::Variant::Original)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let (username, password) = match credentials.as_deref().and_then(|credentials| credentials.split_once(':')) {
+        Some(credentials) => credentials,
+        None => {
+            let response = ApiError::Unauthorized("malformed Basic credentials".to_string()).error_response();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    };
+
+    if let Err(response) = check_login(req.request(), &state, username, password).await {
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    let token = issue_token(&state.session_key, username, BASIC_AUTH_TOKEN_TTL);
+    let value = HeaderValue::from_str(&token).expect("session tokens are plain ASCII");
+    req.headers_mut().insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token)).expect("session tokens are plain ASCII"));
+
+    let mut res = next.call(req).await?;
+    res.headers_mut().insert(HeaderName::from_static(SESSION_TOKEN_HEADER), value);
+    Ok(res.map_into_left_body())
+}
+
+// the connecting peer, or the client a Forwarded / X-Forwarded-For header names when that peer
+// is one of --trusted-proxies; anyone else's header is ignored, since every client can send one
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    if !trusted_proxies.iter().any(|net| net.contains(&peer)) {
+        return Some(peer);
+    }
+    req.connection_info().realip_remote_addr().and_then(parse_client_ip)
+}
+
+// the scheme a Forwarded / X-Forwarded-Proto header names when the peer is one of
+// --trusted-proxies, and otherwise the scheme of the connection itself
+pub fn request_scheme(req: &HttpRequest, trusted_proxies: &[IpNet]) -> String {
+    let trusted = req.peer_addr().is_some_and(|peer| trusted_proxies.iter().any(|net| net.contains(&peer.ip())));
+    if trusted {
+        return req.connection_info().scheme().to_string();
+    }
+    if req.app_config().secure() { "https" } else { "http" }.to_string()
+}
+
+// the address may or may not carry a port depending on where it came from
+pub fn parse_client_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.parse::<IpAddr>())
+        .ok()
+}
+
+// takes the correlation ID from --request-id-header (or makes one up) and echoes it back,
+// where the access log picks it up
+pub async fn propagate_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned().expect("AppState is registered");
+    let header = state.request_id_header.clone();
+
+    let request_id = req
+        .headers()
+        .get(&header)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let mut res = next.call(req).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(header, value);
+    }
+    Ok(res)
+}
@@ -0,0 +1,109 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Deserialize;
+use std::io;
+
+// the file given to `barn export-shares`: one [[notifiers]] entry per share holder, plus the
+// SMTP server the email notifiers send through
+#[derive(Deserialize)]
+pub struct NotifierConfig {
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub notifiers: Vec<NotifierEntry>,
+}
+
+#[derive(Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierEntry {
+    Email { to: String },
+    Sms { phone: String },
+}
+
+impl NotifierEntry {
+    pub fn recipient(&self) -> &str {
+        match self {
+            NotifierEntry::Email { to } => to,
+            NotifierEntry::Sms { phone } => phone,
+        }
+    }
+}
+
+pub trait Notifier {
+    // share is the share file's content as base64; name only identifies it to the recipient
+    fn notify(&self, name: &str, share: &str) -> io::Result<()>;
+}
+
+pub struct EmailNotifier {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Mailbox,
+}
+
+impl EmailNotifier {
+    pub fn new(smtp: &SmtpConfig, to: &str) -> io::Result<Self> {
+        let mut builder = SmtpTransport::starttls_relay(&smtp.host)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+            .port(smtp.port);
+        if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(EmailNotifier { transport: builder.build(), from: parse_mailbox(&smtp.from)?, to: parse_mailbox(to)? })
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn notify(&self, name: &str, share: &str) -> io::Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .subject(format!("barn key share {}", name))
+            .body(format!("Your barn key share ({}):\n\n{}\n\nSubmit it to /unseal when the store needs unsealing.\n", name, share))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.transport.send(&message).map(|_| ()).map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+// no SMS gateway is wired up yet; failing keeps the share from being counted as delivered
+pub struct SmsNotifier {
+    phone: String,
+}
+
+impl Notifier for SmsNotifier {
+    fn notify(&self, name: &str, _share: &str) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("SMS delivery is not implemented, {} was not sent to {}", name, self.phone),
+        ))
+    }
+}
+
+pub fn build(entry: &NotifierEntry, smtp: Option<&SmtpConfig>) -> io::Result<Box<dyn Notifier>> {
+    match entry {
+        NotifierEntry::Email { to } => {
+            let smtp = smtp.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "email notifiers need an [smtp] table"))?;
+            Ok(Box::new(EmailNotifier::new(smtp, to)?))
+        }
+        NotifierEntry::Sms { phone } => Ok(Box::new(SmsNotifier { phone: phone.clone() })),
+    }
+}
+
+fn parse_mailbox(address: &str) -> io::Result<Mailbox> {
+    address
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", address, e)))
+}
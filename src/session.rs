@@ -0,0 +1,77 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, Error, FromRequest, HttpRequest};
+use ring::hmac;
+use sodiumoxide::{base64, hex};
+use std::future::{ready, Ready};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::ApiError;
+use crate::AppState;
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// "<base64url of expiry:username>.<hex HMAC-SHA256 of the same>"
+pub fn issue_token(key: &hmac::Key, username: &str, ttl: u64) -> String {
+    let payload = format!("{}:{}", now() + ttl, username);
+    let tag = hmac::sign(key, payload.as_bytes());
+    format!(
+        "{}.{}",
+        base64::encode(&payload, base64::Variant::UrlSafeNoPadding),
+        hex::encode(tag.as_ref())
+    )
+}
+
+// the username the token was issued to, or why it is not accepted
+pub fn verify_token(key: &hmac::Key, token: &str) -> Result<String, &'static str> {
+    let (payload, tag) = token.rsplit_once('.').ok_or("malformed session token")?;
+    let payload = base64::decode(payload, base64::Variant::UrlSafeNoPadding).map_err(|_| "malformed session token")?;
+    let tag = hex::decode(tag).map_err(|_| "malformed session token")?;
+    hmac::verify(key, &payload, &tag).map_err(|_| "invalid session token")?;
+
+    // the tag checked out, so the payload is one we wrote
+    let payload = String::from_utf8(payload).map_err(|_| "malformed session token")?;
+    let (expires_at, username) = payload.split_once(':').ok_or("malformed session token")?;
+    match expires_at.parse::<u64>() {
+        Ok(expires_at) if expires_at > now() => Ok(username.to_string()),
+        Ok(_) => Err("session token has expired"),
+        Err(_) => Err("malformed session token"),
+    }
+}
+
+// the caller behind a request; username is None for anonymous requests, which are
+// only let through when the server runs without --require-login
+pub struct Session {
+    pub username: Option<String>,
+}
+
+fn unauthorized(message: &str) -> Error {
+    ApiError::Unauthorized(message.to_string()).into()
+}
+
+impl FromRequest for Session {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let state = req.app_data::<web::Data<AppState>>().expect("AppState is registered");
+
+        let token = match req.headers().get(AUTHORIZATION) {
+            Some(value) => match value.to_str().ok().and_then(|value| value.strip_prefix("Bearer ")) {
+                Some(token) => token,
+                None => return ready(Err(unauthorized("Authorization must be a Bearer session token"))),
+            },
+            None if state.require_login => return ready(Err(unauthorized("login required"))),
+            None => return ready(Ok(Session { username: None })),
+        };
+
+        // a token that is sent is always checked, even when login is optional
+        ready(
+            verify_token(&state.session_key, token)
+                .map(|username| Session { username: Some(username) })
+                .map_err(unauthorized),
+        )
+    }
+}
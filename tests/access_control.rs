@@ -0,0 +1,124 @@
+use barn::access_control::{grant_history, user_id, AccessControl, Permission};
+use barn::audit::{verify, AuditLog};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[test]
+fn granted_level_covers_lower_levels_only() {
+    let mut acl = AccessControl::new();
+    let reader = Uuid::new_v4();
+    let writer = Uuid::new_v4();
+    acl.grant_access(reader, "db/password".to_string(), Permission::Read, None).unwrap();
+    acl.grant_access(writer, "db/password".to_string(), Permission::Write, None).unwrap();
+
+    assert!(acl.has_access(reader, "db/password", Permission::Read));
+    assert!(!acl.has_access(reader, "db/password", Permission::Write));
+    assert!(acl.has_access(writer, "db/password", Permission::Read));
+    assert!(acl.has_access(writer, "db/password", Permission::Write));
+    assert!(!acl.has_access(writer, "db/password", Permission::Admin));
+    assert!(!acl.has_access(writer, "db/other", Permission::Read));
+}
+
+#[test]
+fn revoked_grants_no_longer_pass() {
+    let mut acl = AccessControl::new();
+    let user = Uuid::new_v4();
+    acl.grant_access(user, "db/password".to_string(), Permission::Admin, None).unwrap();
+
+    assert!(acl.revoke_access(user, "db/password", None).unwrap());
+    assert!(!acl.revoke_access(user, "db/password", None).unwrap());
+    assert!(!acl.has_access(user, "db/password", Permission::Read));
+}
+
+#[test]
+fn copied_grants_follow_a_cloned_context() {
+    let mut acl = AccessControl::new();
+    let user = Uuid::new_v4();
+    acl.grant_access(user, "app1/db".to_string(), Permission::Write, None).unwrap();
+    acl.grant_access(user, "app10/db".to_string(), Permission::Admin, None).unwrap();
+
+    assert_eq!(acl.copy_grants("app1", "app2", None).unwrap(), 1);
+    assert!(acl.has_access(user, "app2/db", Permission::Write));
+    assert!(!acl.has_access(user, "app20/db", Permission::Read));
+    assert!(acl.has_access(user, "app1/db", Permission::Write));
+    assert_eq!(acl.copy_grants("app1", "app2", None).unwrap(), 0);
+}
+
+#[test]
+fn bulk_grant_skips_paths_already_granted() {
+    let mut acl = AccessControl::new();
+    let user = Uuid::new_v4();
+    acl.grant_access(user, "db/password".to_string(), Permission::Admin, None).unwrap();
+
+    assert_eq!(acl.bulk_grant(user, &["db/password", "db/user", "db/host", "db/user"], Permission::Read, None).unwrap(), 2);
+    assert!(acl.has_access(user, "db/password", Permission::Admin));
+    assert!(acl.has_access(user, "db/host", Permission::Read));
+
+    assert_eq!(acl.bulk_revoke(user, &["db/user", "db/host", "db/missing"], None).unwrap(), 2);
+    assert!(!acl.has_access(user, "db/user", Permission::Read));
+    assert!(acl.has_access(user, "db/password", Permission::Admin));
+}
+
+#[test]
+fn checks_share_the_server_audit_chain() {
+    let path = std::env::temp_dir().join(format!("barn-access-audit-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let log = Arc::new(AuditLog::open(&path).unwrap());
+    let mut acl = AccessControl::new().with_audit_log(log.clone());
+    let alice = user_id("alice");
+    assert_eq!(alice, user_id("alice"));
+    assert_ne!(alice, user_id("bob"));
+    acl.grant_access(alice, "db/password".to_string(), Permission::Read, None).unwrap();
+
+    log.record(Some("alice"), "login", "session", true).unwrap();
+    assert!(acl.has_access(alice, "db/password", Permission::Read));
+    assert!(!acl.has_access(alice, "db/password", Permission::Write));
+    log.record(Some("alice"), "load", "db/password", true).unwrap();
+    // the grant is an entry of its own
+    assert_eq!(verify(&path).unwrap().unwrap(), 5);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn saved_grants_survive_a_reload() {
+    let path = std::env::temp_dir().join(format!("barn-access-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    assert!(!AccessControl::load(&path).unwrap().has_access(user_id("alice"), "db/password", Permission::Read));
+
+    let mut acl = AccessControl::new();
+    acl.bulk_grant(user_id("alice"), &["db/password", "db/user"], Permission::Write, None).unwrap();
+    acl.bulk_revoke(user_id("alice"), &["db/user"], None).unwrap();
+    acl.save(&path).unwrap();
+
+    let reloaded = AccessControl::load(&path).unwrap();
+    assert!(reloaded.has_access(user_id("alice"), "db/password", Permission::Write));
+    assert!(!reloaded.has_access(user_id("alice"), "db/user", Permission::Read));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn grant_changes_are_audited_and_replayed() {
+    let path = std::env::temp_dir().join(format!("barn-access-grants-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    // replaying needs the key names, which are hashed otherwise
+    let log = Arc::new(AuditLog::open(&path).unwrap().with_sensitive_ops(true));
+    let mut acl = AccessControl::new().with_audit_log(log);
+    let alice = user_id("alice");
+
+    acl.grant_access(alice, "db/password".to_string(), Permission::Write, Some("admin")).unwrap();
+    assert_eq!(acl.bulk_grant(alice, &["db/password", "db/user"], Permission::Read, Some("admin")).unwrap(), 1);
+    assert!(acl.revoke_access(alice, "db/password", Some("admin")).unwrap());
+    assert!(!acl.revoke_access(alice, "db/password", Some("admin")).unwrap());
+    assert!(acl.has_access(alice, "db/user", Permission::Read));
+
+    let history = grant_history(&path).unwrap();
+    let replayed: Vec<(&str, Option<Permission>)> = history.iter().map(|change| (change.path.as_str(), change.permission)).collect();
+    assert_eq!(replayed, [("db/password", Some(Permission::Write)), ("db/user", Some(Permission::Read)), ("db/password", None)]);
+    assert!(history.iter().all(|change| change.by.as_deref() == Some("admin") && change.user_id == alice.to_string()));
+    // the has_access check is in the chain too, but is not a grant change
+    assert_eq!(verify(&path).unwrap().unwrap(), 4);
+
+    std::fs::remove_file(&path).unwrap();
+}
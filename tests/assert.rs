@@ -0,0 +1,35 @@
+use barn::kv_silo::{encrypt_data, KVStore};
+use ring::digest::{digest, SHA256};
+use sodiumoxide::hex;
+use std::process::Command;
+
+const KEY: [u8; 32] = [10; 32];
+
+// runs the real binary, which reads data/encryption_key.bin and data/secrets.bin from its working directory
+#[tokio::test]
+async fn assert_prints_only_the_verdict_and_digests() {
+    let dir = std::env::temp_dir().join(format!("barn-assert-{}", std::process::id()));
+    let data_dir = dir.join("data");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(data_dir.join("encryption_key.bin"), KEY).unwrap();
+
+    let store = KVStore::with_data_dir(&data_dir);
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"hunter2").unwrap();
+    store.set_secret("db".to_string(), iv, encrypted_value).await.unwrap();
+    store.persist(data_dir.join("secrets.bin").to_str().unwrap(), &KEY).await.unwrap();
+
+    let run = |expected: &str| Command::new(env!("CARGO_BIN_EXE_barn")).args(["assert", "db", expected]).current_dir(&dir).output().unwrap();
+    let actual = hex::encode(digest(&SHA256, b"hunter2").as_ref());
+
+    let pass = run(&actual);
+    assert!(pass.status.success());
+    assert_eq!(String::from_utf8_lossy(&pass.stdout), "PASS\n");
+
+    let wrong = "0".repeat(64);
+    let fail = run(&wrong);
+    assert_eq!(fail.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&fail.stdout), format!("FAIL: expected {} got {}\n", wrong, actual));
+    assert!(!String::from_utf8_lossy(&fail.stdout).contains("hunter2"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
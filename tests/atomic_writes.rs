@@ -0,0 +1,102 @@
+use barn::kv_silo::{decrypt_data, write_value_file, KVStore};
+use std::io;
+
+const KEY: [u8; 32] = [5; 32];
+
+#[tokio::test]
+async fn interrupted_write_leaves_previous_file_intact() {
+    let dir = std::env::temp_dir().join(format!("barn-atomic-writes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    store.set_secret("a".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    store.persist(path, &KEY).await.unwrap();
+
+    // a crash between writing the temp file and renaming it leaves just a partial temp file behind
+    let good = std::fs::read(path).unwrap();
+    std::fs::write(dir.join("secrets.bin.tmp"), &good[..good.len() / 2]).unwrap();
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &KEY).await.unwrap();
+    assert_eq!(restored.list_keys().await, ["a"]);
+
+    // later saves use temp files of their own and leave the leftover alone
+    store.persist(path, &KEY).await.unwrap();
+    restored.restore(path, &KEY).await.unwrap();
+    assert_eq!(restored.list_keys().await, ["a"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn truncated_file_is_reported() {
+    let dir = std::env::temp_dir().join(format!("barn-truncated-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    store.persist(path, &KEY).await.unwrap();
+    let good = std::fs::read(path).unwrap();
+
+    for len in [3, 12, good.len() - 1] {
+        std::fs::write(path, &good[..len]).unwrap();
+        let err = store.load_from_file_encrypted(path).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "truncated to {} bytes", len);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_persists_never_leave_a_torn_file() {
+    let dir = std::env::temp_dir().join(format!("barn-concurrent-writes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin").to_str().unwrap().to_string();
+
+    let store = std::sync::Arc::new(KVStore::with_data_dir(&dir));
+    let writers: Vec<_> = (0..16)
+        .map(|i| {
+            let (store, path) = (store.clone(), path.clone());
+            tokio::spawn(async move {
+                store.set_secret(format!("key{}", i), vec![0; 24], vec![0; 4096]).await.unwrap();
+                store.persist(&path, &KEY).await.unwrap();
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(&path, &KEY).await.unwrap();
+    assert_eq!(restored.list_keys().await.len(), 16);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// the per-key file /store and /store/batch write
+#[test]
+fn interrupted_store_leaves_previous_value_intact() {
+    let dir = std::env::temp_dir().join(format!("barn-atomic-store-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("greeting.dat");
+    let read = || {
+        let blob = std::fs::read(&path).unwrap();
+        decrypt_data(&KEY, &blob[..24], &blob[24..]).unwrap().to_vec()
+    };
+
+    write_value_file(&path, &KEY, b"hello").unwrap();
+    let good = std::fs::read(&path).unwrap();
+    std::fs::write(dir.join("greeting.dat.tmp"), &good[..good.len() / 2]).unwrap();
+    assert_eq!(read(), b"hello");
+
+    write_value_file(&path, &KEY, b"goodbye").unwrap();
+    assert_eq!(read(), b"goodbye");
+    // only the leftover from the simulated crash is beside it, the write cleaned up after itself
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
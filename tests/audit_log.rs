@@ -0,0 +1,71 @@
+use barn::audit::{entries, verify, AuditLog};
+
+#[test]
+fn edited_entry_breaks_the_chain() {
+    let path = std::env::temp_dir().join(format!("barn-audit-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let log = AuditLog::open(&path).unwrap();
+    log.record(Some("alice"), "store", "db/password", true).unwrap();
+    log.record(None, "load", "db/password", false).unwrap();
+    // reopening continues the chain from the last entry
+    AuditLog::open(&path).unwrap().record(Some("bob"), "load", "db/password", true).unwrap();
+    assert_eq!(verify(&path).unwrap().unwrap(), 3);
+
+    let edited = std::fs::read_to_string(&path).unwrap().replacen("\"allowed\":false", "\"allowed\":true", 1);
+    std::fs::write(&path, edited).unwrap();
+    assert_eq!(verify(&path).unwrap().unwrap_err().line, 2);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn truncated_log_continues_the_chain_from_the_old_file() {
+    let path = std::env::temp_dir().join(format!("barn-audit-truncate-{}.log", std::process::id()));
+    let old_path = path.with_extension("log.old");
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&old_path);
+
+    let log = AuditLog::open(&path).unwrap().with_truncate_at(Some(1));
+    log.record(Some("alice"), "store", "db/password", true).unwrap();
+    log.record(Some("alice"), "load", "db/password", true).unwrap();
+
+    // the second record moved the first to .old and started with the marker
+    assert_eq!(verify(&old_path).unwrap().unwrap(), 1);
+    let current = std::fs::read_to_string(&path).unwrap();
+    assert!(current.lines().next().unwrap().contains("\"action\":\"log_truncated\""));
+    assert_eq!(verify(&path).unwrap().unwrap(), 2);
+
+    // without the file it continues, the marker can't be trusted
+    std::fs::remove_file(&old_path).unwrap();
+    assert_eq!(verify(&path).unwrap().unwrap_err().line, 1);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn key_names_are_hashed_unless_sensitive_ops_are_logged() {
+    let path = std::env::temp_dir().join(format!("barn-audit-sensitive-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    AuditLog::open(&path).unwrap().record_key(Some("alice"), "store", "db/password", true, Some(b"hunter2")).unwrap();
+    AuditLog::open(&path).unwrap().with_sensitive_ops(true).record_key(Some("alice"), "store", "db/password", true, Some(b"hunter2")).unwrap();
+
+    let entries = entries(&path).unwrap();
+    assert!(entries[0].resource.starts_with("sha256:"));
+    assert_eq!(entries[1].resource, "db/password");
+    // the value is never written, only its hash
+    let value_sha256 = "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7";
+    assert!(entries.iter().all(|entry| entry.value_sha256.as_deref() == Some(value_sha256)));
+    assert!(!std::fs::read_to_string(&path).unwrap().contains("hunter2"));
+    assert_eq!(verify(&path).unwrap().unwrap(), 2);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn disabled_log_writes_nothing() {
+    let log = AuditLog::disabled();
+    log.record(Some("alice"), "login", "session", true).unwrap();
+    log.record_key(Some("alice"), "store", "db/password", true, Some(b"hunter2")).unwrap();
+}
@@ -0,0 +1,19 @@
+use barn::kv_silo::KVStore;
+
+#[tokio::test]
+async fn batch_delete_reports_which_keys_existed() {
+    let store = KVStore::new();
+    for key in ["a", "b", "c"] {
+        store.set_secret(key.to_string(), vec![0; 24], vec![1]).await.unwrap();
+    }
+    store.set_secret("a".to_string(), vec![0; 24], vec![2]).await.unwrap();
+
+    let deleted = store.batch_delete(&["a", "c", "missing"]).await;
+    assert_eq!(deleted.len(), 3);
+    assert!(deleted["a"] && deleted["c"] && !deleted["missing"]);
+    assert_eq!(store.list_keys().await, ["b"]);
+
+    // history goes with the key, so a new "a" starts without the old versions
+    store.set_secret("a".to_string(), vec![0; 24], vec![3]).await.unwrap();
+    assert_eq!(store.list_versions("a").await.len(), 1);
+}
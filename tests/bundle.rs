@@ -0,0 +1,100 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, KVStore, MergePolicy};
+
+const SOURCE_KEY: [u8; 32] = [7; 32];
+const DEST_KEY: [u8; 32] = [8; 32];
+
+async fn set(store: &KVStore, master_key: &[u8], key: &str, value: &[u8]) {
+    let (iv, encrypted_value) = encrypt_data(master_key, value).unwrap();
+    store.set_secret(key.to_string(), iv, encrypted_value).await.unwrap();
+}
+
+async fn get(store: &KVStore, master_key: &[u8], key: &str) -> Vec<u8> {
+    let secret = store.get_secret(key).await.unwrap();
+    decrypt_data(master_key, &secret.iv, &secret.encrypted_value).unwrap().to_vec()
+}
+
+#[tokio::test]
+async fn bundle_moves_secrets_to_a_different_master_key() {
+    let source = KVStore::new();
+    set(&source, &SOURCE_KEY, "db", b"old").await;
+    set(&source, &SOURCE_KEY, "db", b"new").await;
+    let bundle = source.export_bundle(&SOURCE_KEY, "correct horse").await.unwrap();
+
+    let dest = KVStore::new();
+    assert_eq!(dest.import_bundle(&bundle, "correct horse", &DEST_KEY, MergePolicy::SkipExisting).await.unwrap(), 1);
+    assert_eq!(get(&dest, &DEST_KEY, "db").await, b"new");
+
+    let previous = dest.get_secret_version("db", 1).await.unwrap();
+    assert_eq!(&decrypt_data(&DEST_KEY, &previous.iv, &previous.encrypted_value).unwrap()[..], b"old");
+}
+
+#[tokio::test]
+async fn wrong_passphrase_is_rejected() {
+    let source = KVStore::new();
+    set(&source, &SOURCE_KEY, "db", b"value").await;
+    let bundle = source.export_bundle(&SOURCE_KEY, "correct horse").await.unwrap();
+
+    let dest = KVStore::new();
+    assert!(dest.import_bundle(&bundle, "battery staple", &DEST_KEY, MergePolicy::Overwrite).await.is_err());
+    assert!(dest.list_keys().await.is_empty());
+}
+
+#[tokio::test]
+async fn merge_policy_decides_conflicts() {
+    let source = KVStore::new();
+    set(&source, &SOURCE_KEY, "db", b"bundled").await;
+    set(&source, &SOURCE_KEY, "api", b"bundled").await;
+    let bundle = source.export_bundle(&SOURCE_KEY, "correct horse").await.unwrap();
+
+    let dest = KVStore::new();
+    set(&dest, &DEST_KEY, "db", b"local").await;
+    assert_eq!(dest.import_bundle(&bundle, "correct horse", &DEST_KEY, MergePolicy::SkipExisting).await.unwrap(), 1);
+    assert_eq!(get(&dest, &DEST_KEY, "db").await, b"local");
+    assert_eq!(get(&dest, &DEST_KEY, "api").await, b"bundled");
+
+    assert_eq!(dest.import_bundle(&bundle, "correct horse", &DEST_KEY, MergePolicy::Overwrite).await.unwrap(), 2);
+    assert_eq!(get(&dest, &DEST_KEY, "db").await, b"bundled");
+}
+
+#[tokio::test]
+async fn bad_entry_leaves_the_store_untouched() {
+    let source = KVStore::new();
+    set(&source, &SOURCE_KEY, "db", b"value").await;
+    set(&source, &SOURCE_KEY, "much-too-long", b"value").await;
+    let bundle = source.export_bundle(&SOURCE_KEY, "correct horse").await.unwrap();
+
+    let dest = KVStore::new().with_max_key_length(4);
+    assert!(dest.import_bundle(&bundle, "correct horse", &DEST_KEY, MergePolicy::Overwrite).await.is_err());
+    assert!(dest.list_keys().await.is_empty());
+}
+
+#[tokio::test]
+async fn imported_history_is_bounded_by_max_versions() {
+    let source = KVStore::new().with_max_versions(10);
+    for value in 1..=6u8 {
+        set(&source, &SOURCE_KEY, "db", &[value]).await;
+    }
+    let bundle = source.export_bundle(&SOURCE_KEY, "correct horse").await.unwrap();
+
+    let dest = KVStore::new().with_max_versions(3);
+    dest.import_bundle(&bundle, "correct horse", &DEST_KEY, MergePolicy::Overwrite).await.unwrap();
+    let versions: Vec<Vec<u8>> = dest
+        .list_versions("db")
+        .await
+        .iter()
+        .map(|secret| decrypt_data(&DEST_KEY, &secret.iv, &secret.encrypted_value).unwrap().to_vec())
+        .collect();
+    assert_eq!(versions, [[6], [5], [4]]);
+}
+
+#[tokio::test]
+async fn costly_key_derivation_is_refused() {
+    let source = KVStore::new();
+    set(&source, &SOURCE_KEY, "db", b"value").await;
+    let mut bundle = source.export_bundle(&SOURCE_KEY, "correct horse").await.unwrap();
+    // the memory cost follows the 8-byte magic and the version byte
+    bundle[9..13].copy_from_slice(&u32::MAX.to_be_bytes());
+
+    let err = KVStore::new().import_bundle(&bundle, "correct horse", &DEST_KEY, MergePolicy::Overwrite).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
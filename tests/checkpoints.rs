@@ -0,0 +1,40 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, KVStore};
+use std::io;
+
+const KEY: [u8; 32] = [6; 32];
+
+#[tokio::test]
+async fn checkpoint_round_trips() {
+    let dir = std::env::temp_dir().join(format!("barn-checkpoints-{}", std::process::id()));
+    let store = KVStore::with_data_dir(&dir);
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"before").unwrap();
+    store.set_secret("db".to_string(), iv, encrypted_value).await.unwrap();
+    store.checkpoint("release-1", &KEY).await.unwrap();
+
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"after").unwrap();
+    store.set_secret("db".to_string(), iv, encrypted_value).await.unwrap();
+    store.set_secret("api".to_string(), vec![0; 24], vec![1]).await.unwrap();
+
+    let entries: Vec<_> = std::fs::read_dir(dir.join("checkpoints")).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(entries.len(), 1);
+    let name = entries[0].file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with("release-1_") && name.ends_with(".bin"), "{}", name);
+
+    store.restore_checkpoint(entries[0].to_str().unwrap(), &KEY).await.unwrap();
+    assert_eq!(store.list_keys().await, ["db"]);
+    let secret = store.get_secret("db").await.unwrap();
+    assert_eq!(&decrypt_data(&KEY, &secret.iv, &secret.encrypted_value).unwrap()[..], b"before");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn labels_cannot_leave_the_checkpoints_directory() {
+    let dir = std::env::temp_dir().join(format!("barn-checkpoint-labels-{}", std::process::id()));
+    let store = KVStore::with_data_dir(&dir);
+    for label in ["", "../escape", "a/b", "a\\b", ".."] {
+        let err = store.checkpoint(label, &KEY).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?} was accepted", label);
+    }
+    assert!(!dir.exists());
+}
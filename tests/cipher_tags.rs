@@ -0,0 +1,88 @@
+use barn::kv_silo::{encrypt_data, Algorithm, KVStore, PersistedSecrets};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const KEY: [u8; 32] = [9; 32];
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("barn-cipher-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn round_trip(algorithm: Algorithm, name: &str) {
+    let dir = temp_dir(name);
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir).with_algorithm(algorithm);
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"hunter2").unwrap();
+    store.set_secret("password".to_string(), iv.clone(), encrypted_value).await.unwrap();
+    store.persist(path, &KEY).await.unwrap();
+
+    // magic, version, then the algorithm tag
+    assert_eq!(std::fs::read(path).unwrap()[5], algorithm.tag());
+    let (file_iv, _) = store.load_from_file_encrypted(path).await.unwrap();
+    assert_eq!(file_iv.len(), algorithm.cipher().nonce_len());
+
+    // a store configured with the default cipher still reads it
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &KEY).await.unwrap();
+    assert_eq!(restored.get_secret("password").await.unwrap().iv, iv);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn xchacha20_poly1305_file_round_trips() {
+    round_trip(Algorithm::XChaCha20Poly1305, "xchacha").await;
+}
+
+#[tokio::test]
+async fn aes_256_gcm_file_round_trips() {
+    round_trip(Algorithm::Aes256Gcm, "aes").await;
+}
+
+#[tokio::test]
+async fn untagged_file_is_read_as_xchacha20_poly1305() {
+    let dir = temp_dir("untagged");
+    let path = dir.join("secrets.bin");
+
+    let plaintext = serde_json::to_vec(&PersistedSecrets { secrets: HashMap::new(), history: HashMap::new() }).unwrap();
+    let (iv, encrypted_data) = encrypt_data(&KEY, &plaintext).unwrap();
+    let mut contents = b"BARN\x01".to_vec();
+    for part in [&iv, &encrypted_data] {
+        contents.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        contents.extend_from_slice(part);
+    }
+    std::fs::write(&path, contents).unwrap();
+
+    let store = KVStore::with_data_dir(&dir).with_algorithm(Algorithm::Aes256Gcm);
+    store.restore(path.to_str().unwrap(), &KEY).await.unwrap();
+    assert!(store.list_keys().await.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn restored_store_keeps_writing_the_files_cipher() {
+    let dir = temp_dir("keep");
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    KVStore::with_data_dir(&dir).with_algorithm(Algorithm::Aes256Gcm).persist(path, &KEY).await.unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    store.restore(path, &KEY).await.unwrap();
+    assert_eq!(store.algorithm(), Algorithm::Aes256Gcm);
+    store.persist(path, &KEY).await.unwrap();
+    assert_eq!(std::fs::read(path).unwrap()[5], Algorithm::Aes256Gcm.tag());
+
+    // an explicitly chosen cipher still wins, which is how --cipher converts a file
+    let pinned = KVStore::with_data_dir(&dir).with_algorithm(Algorithm::XChaCha20Poly1305);
+    pinned.restore(path, &KEY).await.unwrap();
+    pinned.persist(path, &KEY).await.unwrap();
+    assert_eq!(std::fs::read(path).unwrap()[5], Algorithm::XChaCha20Poly1305.tag());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
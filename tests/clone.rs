@@ -0,0 +1,22 @@
+use std::process::Command;
+
+// neither check needs a key or a silo, so nothing is set up in the working directory
+#[test]
+fn clone_refuses_re_encrypt_and_a_running_server() {
+    let dir = std::env::temp_dir().join(format!("barn-clone-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_barn")).arg("clone").args(args).current_dir(&dir).output().unwrap();
+
+    let re_encrypt = run(&["staging", "copy", "--re-encrypt"]);
+    assert!(!re_encrypt.status.success());
+    assert!(String::from_utf8_lossy(&re_encrypt.stderr).contains("no per-context DEK"));
+
+    // this test's own process stands in for the server
+    std::fs::write(dir.join("barn.pid"), std::process::id().to_string()).unwrap();
+    let running = run(&["staging", "copy", "--pid-file", "barn.pid"]);
+    assert!(!running.status.success());
+    assert!(String::from_utf8_lossy(&running.stderr).contains(&format!("still running (PID {})", std::process::id())));
+    assert!(!dir.join("data").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
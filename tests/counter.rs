@@ -0,0 +1,41 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, CounterError, KVStore};
+use std::sync::Arc;
+
+const KEY: [u8; 32] = [2; 32];
+
+#[tokio::test]
+async fn counter_starts_from_zero_and_keeps_history() {
+    let store = KVStore::new();
+    assert_eq!(store.atomic_increment_counter("hits", &KEY, 5).await.unwrap(), 5);
+    assert_eq!(store.atomic_increment_counter("hits", &KEY, -7).await.unwrap(), -2);
+
+    let secret = store.get_secret("hits").await.unwrap();
+    assert_eq!(&decrypt_data(&KEY, &secret.iv, &secret.encrypted_value).unwrap()[..], b"-2");
+    assert_eq!(store.list_versions("hits").await.len(), 2);
+}
+
+#[tokio::test]
+async fn concurrent_increments_are_not_lost() {
+    let store = Arc::new(KVStore::new());
+    let tasks: Vec<_> = (0..50)
+        .map(|_| {
+            let store = store.clone();
+            tokio::spawn(async move { store.atomic_increment_counter("hits", &KEY, 1).await.unwrap() })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(store.atomic_increment_counter("hits", &KEY, 0).await.unwrap(), 50);
+}
+
+#[tokio::test]
+async fn non_numeric_and_overflowing_values_are_rejected() {
+    let store = KVStore::new();
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"hunter2").unwrap();
+    store.set_secret("password".to_string(), iv, encrypted_value).await.unwrap();
+    assert!(matches!(store.atomic_increment_counter("password", &KEY, 1).await, Err(CounterError::NotNumeric)));
+
+    store.atomic_increment_counter("big", &KEY, i64::MAX).await.unwrap();
+    assert!(matches!(store.atomic_increment_counter("big", &KEY, 1).await, Err(CounterError::Overflow)));
+}
@@ -0,0 +1,43 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, CryptoError, KVStore};
+use std::io;
+
+const KEY: [u8; 32] = [42; 32];
+
+#[test]
+fn flipped_ciphertext_byte_is_an_error() {
+    let (iv, mut ciphertext) = encrypt_data(&KEY, b"top secret").unwrap();
+    ciphertext[3] ^= 0x01;
+
+    assert!(matches!(decrypt_data(&KEY, &iv, &ciphertext), Err(CryptoError::AuthenticationFailed)));
+}
+
+#[test]
+fn bad_key_and_nonce_lengths_are_errors() {
+    let (iv, ciphertext) = encrypt_data(&KEY, b"top secret").unwrap();
+
+    assert!(matches!(encrypt_data(&KEY[..16], b"x"), Err(CryptoError::InvalidKeyLength(16))));
+    assert!(matches!(decrypt_data(&KEY, &iv[..12], &ciphertext), Err(CryptoError::InvalidNonceLength { expected: 24, got: 12 })));
+}
+
+#[tokio::test]
+async fn restore_tells_tampered_file_from_missing_file() {
+    let dir = std::env::temp_dir().join(format!("barn-crypto-errors-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    store.set_secret("a".to_string(), vec![1; 24], vec![2; 16]).await.unwrap();
+    store.persist(path, &KEY).await.unwrap();
+
+    let mut bytes = std::fs::read(path).unwrap();
+    *bytes.last_mut().unwrap() ^= 0x01;
+    std::fs::write(path, bytes).unwrap();
+
+    let tampered = store.restore(path, &KEY).await.unwrap_err();
+    assert_eq!(tampered.kind(), io::ErrorKind::InvalidData);
+    let missing = store.restore(dir.join("absent.bin").to_str().unwrap(), &KEY).await.unwrap_err();
+    assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,45 @@
+use barn::kv_silo::KVStore;
+
+const KEY: [u8; 32] = [3; 32];
+
+#[tokio::test]
+async fn disabled_secret_reads_as_absent_but_is_kept() {
+    let store = KVStore::new();
+    store.set_secret("db".to_string(), vec![0; 24], vec![1]).await.unwrap();
+
+    assert!(store.disable_secret("db").await);
+    assert!(store.is_disabled("db").await);
+    assert!(store.get_secret("db").await.is_none());
+    assert_eq!(store.list_versions("db").await.len(), 1);
+
+    assert!(store.enable_secret("db").await);
+    assert!(!store.is_disabled("db").await);
+    assert_eq!(store.get_secret("db").await.unwrap().encrypted_value, [1]);
+}
+
+#[tokio::test]
+async fn missing_key_cannot_be_disabled() {
+    let store = KVStore::new();
+    assert!(!store.disable_secret("db").await);
+    assert!(!store.enable_secret("db").await);
+    assert!(!store.is_disabled("db").await);
+}
+
+#[tokio::test]
+async fn disabled_flag_survives_a_restart() {
+    let dir = std::env::temp_dir().join(format!("barn-disabled-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    store.set_secret("db".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    store.disable_secret("db").await;
+    store.persist(path, &KEY).await.unwrap();
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &KEY).await.unwrap();
+    assert!(restored.is_disabled("db").await);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
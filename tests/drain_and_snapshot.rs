@@ -0,0 +1,51 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, KVStore};
+use tokio::io::AsyncReadExt;
+
+const KEY: [u8; 32] = [11; 32];
+
+#[tokio::test]
+async fn drain_empties_secrets_and_history() {
+    let store = KVStore::new();
+    store.set_secret("db".to_string(), vec![1; 24], vec![1]).await.unwrap();
+    store.set_secret("db".to_string(), vec![2; 24], vec![2]).await.unwrap();
+    store.set_secret("api".to_string(), vec![3; 24], vec![3]).await.unwrap();
+
+    let mut drained = store.drain_to_vec().await;
+    drained.sort_by(|a, b| a.0.cmp(&b.0));
+    let drained: Vec<_> = drained.into_iter().map(|(key, secret)| (key, secret.encrypted_value)).collect();
+    assert_eq!(drained, [("api".to_string(), vec![3]), ("db".to_string(), vec![2])]);
+    assert!(store.list_keys().await.is_empty());
+
+    store.set_secret("db".to_string(), vec![4; 24], vec![4]).await.unwrap();
+    let versions: Vec<Vec<u8>> = store.list_versions("db").await.into_iter().map(|secret| secret.encrypted_value).collect();
+    assert_eq!(versions, [[4]]);
+}
+
+#[tokio::test]
+async fn snapshot_matches_what_persist_writes() {
+    let dir = std::env::temp_dir().join(format!("barn-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = KVStore::new();
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"hunter2").unwrap();
+    store.set_secret("db".to_string(), iv, encrypted_value).await.unwrap();
+
+    let mut snapshot = Vec::new();
+    store.snapshot_to_reader(&KEY).await.unwrap().read_to_end(&mut snapshot).await.unwrap();
+    let snapshot_file = dir.join("snapshot.bin");
+    std::fs::write(&snapshot_file, &snapshot).unwrap();
+    let persisted_file = dir.join("persisted.bin");
+    store.persist(persisted_file.to_str().unwrap(), &KEY).await.unwrap();
+
+    // both carry a fresh iv, so they are compared by what they decrypt to
+    assert_eq!(
+        store.decrypt_file(snapshot_file.to_str().unwrap(), &KEY).await.unwrap(),
+        store.decrypt_file(persisted_file.to_str().unwrap(), &KEY).await.unwrap()
+    );
+
+    let restored = KVStore::new();
+    restored.restore(snapshot_file.to_str().unwrap(), &KEY).await.unwrap();
+    let secret = restored.get_secret("db").await.unwrap();
+    assert_eq!(&decrypt_data(&KEY, &secret.iv, &secret.encrypted_value).unwrap()[..], b"hunter2");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,34 @@
+use barn::kv_silo::{encrypt_data, KVStore};
+use std::process::Command;
+
+const KEY: [u8; 32] = [9; 32];
+
+// runs the real binary, which reads data/encryption_key.bin and data/secrets.bin from its working directory
+#[tokio::test]
+async fn exporting_everything_skips_disabled_secrets() {
+    let dir = std::env::temp_dir().join(format!("barn-env-file-{}", std::process::id()));
+    let data_dir = dir.join("data");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(data_dir.join("encryption_key.bin"), KEY).unwrap();
+
+    let store = KVStore::with_data_dir(&data_dir);
+    for (key, value) in [("DB_PASSWORD", "hunter2"), ("API_TOKEN", "abc123")] {
+        let (iv, encrypted_value) = encrypt_data(&KEY, value.as_bytes()).unwrap();
+        store.set_secret(key.to_string(), iv, encrypted_value).await.unwrap();
+    }
+    store.disable_secret("API_TOKEN").await;
+    store.persist(data_dir.join("secrets.bin").to_str().unwrap(), &KEY).await.unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_barn"))
+        .args(["export-env-file", "out.env"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let contents = std::fs::read_to_string(dir.join("out.env")).unwrap();
+    assert!(contents.contains("DB_PASSWORD=hunter2\n"));
+    assert!(!contents.contains("API_TOKEN"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
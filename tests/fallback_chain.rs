@@ -0,0 +1,55 @@
+use barn::kv_silo::{encrypt_data, KVStore};
+
+const KEY: [u8; 32] = [5; 32];
+const OTHER_KEY: [u8; 32] = [6; 32];
+
+async fn set(store: &KVStore, master_key: &[u8], key: &str, value: u8) {
+    let (iv, encrypted_value) = encrypt_data(master_key, &[value]).unwrap();
+    store.set_secret(key.to_string(), iv, encrypted_value).await.unwrap();
+}
+
+async fn with(key: &str, value: u8) -> KVStore {
+    let store = KVStore::new();
+    set(&store, &KEY, key, value).await;
+    store
+}
+
+async fn served_by(store: &KVStore, key: &str, chain: &[&KVStore]) -> Option<usize> {
+    store.get_secret_with_fallback_index(key, chain, &KEY).await.map(|(i, _)| i)
+}
+
+#[tokio::test]
+async fn first_store_holding_the_key_wins() {
+    let prod = with("db", 1).await;
+    let staging = with("api", 2).await;
+    let defaults = with("api", 3).await;
+    set(&defaults, &KEY, "timeout", 3).await;
+    let chain = [&staging, &defaults];
+
+    assert_eq!(served_by(&prod, "db", &chain).await, Some(0));
+    assert_eq!(served_by(&prod, "api", &chain).await, Some(1));
+    assert_eq!(served_by(&prod, "timeout", &chain).await, Some(2));
+    assert!(prod.get_secret_with_fallback_chain("missing", &chain, &KEY).await.is_none());
+
+    let secret = prod.get_secret_with_fallback_chain("api", &chain, &KEY).await.unwrap();
+    assert_eq!(secret.encrypted_value, staging.get_secret("api").await.unwrap().encrypted_value);
+}
+
+#[tokio::test]
+async fn disabled_secret_falls_through() {
+    let prod = with("db", 1).await;
+    let defaults = with("db", 2).await;
+    prod.disable_secret("db").await;
+
+    assert_eq!(served_by(&prod, "db", &[&defaults]).await, Some(1));
+}
+
+#[tokio::test]
+async fn secret_under_another_key_falls_through() {
+    let prod = KVStore::new();
+    set(&prod, &OTHER_KEY, "db", 1).await;
+    let defaults = with("db", 2).await;
+
+    assert_eq!(served_by(&prod, "db", &[&defaults]).await, Some(1));
+    assert!(prod.get_secret_with_fallback_chain("db", &[], &KEY).await.is_none());
+}
@@ -0,0 +1,18 @@
+use std::process::Command;
+
+#[test]
+fn plain_http_urls_are_refused_before_anything_is_stored() {
+    let dir = std::env::temp_dir().join(format!("barn-fetch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_barn"))
+        .args(["fetch", "http://127.0.0.1:9/ca.pem", "ca-cert", "--insecure"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Only https URLs can be fetched"));
+    assert!(!dir.join("data").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,27 @@
+use barn::kv_silo::KVStore;
+
+const KEY: [u8; 32] = [3; 32];
+
+#[tokio::test]
+async fn keys_are_sorted_and_deletes_are_persisted() {
+    let dir = std::env::temp_dir().join(format!("barn-list-keys-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    for key in ["b", "c", "a"] {
+        store.set_secret(key.to_string(), vec![0; 24], vec![1]).await.unwrap();
+    }
+    assert_eq!(store.list_keys().await, ["a", "b", "c"]);
+
+    assert!(store.delete_secret("b").await);
+    assert!(!store.delete_secret("b").await);
+    store.persist(path, &KEY).await.unwrap();
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &KEY).await.unwrap();
+    assert_eq!(restored.list_keys().await, ["a", "c"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
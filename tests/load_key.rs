@@ -0,0 +1,18 @@
+use barn::kv_silo::{get_or_create_key, load_key};
+use std::io;
+
+#[test]
+fn load_key_never_creates_a_key() {
+    let path = std::env::temp_dir().join(format!("barn-load-key-{}.bin", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    assert_eq!(load_key(path).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(!std::path::Path::new(path).exists());
+
+    let created = get_or_create_key(path).unwrap();
+    assert_eq!(load_key(path).unwrap(), created);
+
+    std::fs::write(path, [1u8; 16]).unwrap();
+    assert_eq!(load_key(path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,44 @@
+use barn::login_throttle::LoginThrottle;
+use std::net::{IpAddr, Ipv4Addr};
+
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+async fn attempt(throttle: &LoginThrottle, username: &str, authenticated: bool) -> Result<(), u64> {
+    let keys = LoginThrottle::keys(username, Some(CLIENT));
+    throttle.begin(&keys).await?;
+    throttle.finish(&keys, authenticated).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn success_from_the_same_ip_does_not_reset_the_ip_counter() {
+    let throttle = LoginThrottle::new(5, 300, 3600);
+    for username in ["bob", "carol", "dave", "erin"] {
+        attempt(&throttle, username, false).await.unwrap();
+    }
+    // a valid login clears mallory's own counter but not the address it came from
+    attempt(&throttle, "mallory", true).await.unwrap();
+    attempt(&throttle, "frank", false).await.unwrap();
+
+    assert_eq!(attempt(&throttle, "grace", false).await.unwrap_err(), 30);
+    assert_eq!(attempt(&throttle, "mallory", true).await.unwrap_err(), 30);
+}
+
+#[tokio::test]
+async fn concurrent_attempts_are_reserved_before_the_password_is_checked() {
+    let throttle = LoginThrottle::new(3, 300, 3600);
+    let keys = LoginThrottle::keys("alice", Some(CLIENT));
+
+    // none has finished yet, so only max_failures of them may go on to bcrypt
+    let mut started = 0;
+    for _ in 0..10 {
+        if throttle.begin(&keys).await.is_ok() {
+            started += 1;
+        }
+    }
+    assert_eq!(started, 3);
+    for _ in 0..3 {
+        throttle.finish(&keys, false).await;
+    }
+    assert_eq!(throttle.begin(&keys).await.unwrap_err(), 30);
+}
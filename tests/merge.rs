@@ -0,0 +1,38 @@
+use barn::kv_silo::KVStore;
+
+const KEY: [u8; 32] = [6; 32];
+
+#[tokio::test]
+async fn resolver_picks_the_winner_for_conflicting_keys() {
+    let dir = std::env::temp_dir().join(format!("barn-merge-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("remote.bin");
+    let path = path.to_str().unwrap();
+
+    let remote = KVStore::with_data_dir(&dir);
+    remote.set_secret("shared".to_string(), vec![2; 24], b"remote".to_vec()).await.unwrap();
+    remote.set_secret("kept".to_string(), vec![2; 24], b"remote".to_vec()).await.unwrap();
+    remote.set_secret("only-remote".to_string(), vec![2; 24], b"remote".to_vec()).await.unwrap();
+    remote.persist(path, &KEY).await.unwrap();
+
+    let local = KVStore::with_data_dir(&dir);
+    local.set_secret("shared".to_string(), vec![1; 24], b"local".to_vec()).await.unwrap();
+    local.set_secret("kept".to_string(), vec![1; 24], b"local".to_vec()).await.unwrap();
+
+    local
+        .merge_with_conflict_resolver(path, &KEY, |key, local, remote| {
+            if key == "kept" { local.clone() } else { remote.clone() }
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(local.get_secret("shared").await.unwrap().encrypted_value, b"remote");
+    assert_eq!(local.get_secret("kept").await.unwrap().encrypted_value, b"local");
+    assert_eq!(local.get_secret("only-remote").await.unwrap().encrypted_value, b"remote");
+
+    // the overwritten local value is still reachable, the one that won is not duplicated
+    assert_eq!(local.get_secret_version("shared", 1).await.unwrap().encrypted_value, b"local");
+    assert_eq!(local.list_versions("kept").await.len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
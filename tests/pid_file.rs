@@ -0,0 +1,54 @@
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+fn serve(dir: &std::path::Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_barn"));
+    command.args(["serve", "--address", "127.0.0.1:0", "--pid-file", "barn.pid"]).current_dir(dir);
+    command
+}
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn stop(mut child: Child) {
+    Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn pid_file_is_claimed_once_and_only_removed_by_its_owner() {
+    let dir = std::env::temp_dir().join(format!("barn-pid-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pid_file = dir.join("barn.pid");
+
+    // this test's own process stands in for a server that is still running
+    std::fs::write(&pid_file, format!("{}\n", std::process::id())).unwrap();
+    let refused = serve(&dir).output().unwrap();
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains(&format!("already running (PID {})", std::process::id())));
+
+    // a file from a process that has exited is replaced
+    let mut exited = Command::new("true").spawn().unwrap();
+    exited.wait().unwrap();
+    std::fs::write(&pid_file, format!("{}\n", exited.id())).unwrap();
+    let server = serve(&dir).spawn().unwrap();
+    let server_pid = server.id().to_string();
+    wait_for("the PID file", || std::fs::read_to_string(&pid_file).is_ok_and(|contents| contents.trim() == server_pid));
+    stop(server);
+    assert!(!pid_file.exists());
+
+    // once another process has taken the file over, shutting down leaves it alone
+    let server = serve(&dir).spawn().unwrap();
+    let server_pid = server.id().to_string();
+    wait_for("the PID file", || std::fs::read_to_string(&pid_file).is_ok_and(|contents| contents.trim() == server_pid));
+    std::fs::write(&pid_file, "1\n").unwrap();
+    stop(server);
+    assert_eq!(std::fs::read_to_string(&pid_file).unwrap(), "1\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,13 @@
+use barn::access_control::user_id;
+use barn::rate_limit::UserRateLimiter;
+
+#[test]
+fn each_user_has_a_bucket_of_their_own() {
+    let limiter = UserRateLimiter::new(3);
+    for _ in 0..3 {
+        limiter.check(user_id("alice")).unwrap();
+    }
+    // a token comes back every 20 seconds at 3 a minute
+    assert_eq!(limiter.check(user_id("alice")).unwrap_err(), 20);
+    limiter.check(user_id("bob")).unwrap();
+}
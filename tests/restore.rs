@@ -0,0 +1,42 @@
+use barn::audit::{AuditLog, DELETE_ACTION};
+use barn::kv_silo::{decrypt_data, encrypt_data, KVStore};
+use std::process::Command;
+
+const KEY: [u8; 32] = [12; 32];
+
+// runs the real binary, which reads data/encryption_key.bin from its working directory
+#[tokio::test]
+async fn restore_replays_writes_and_deletes_from_the_audit_log() {
+    let dir = std::env::temp_dir().join(format!("barn-restore-{}", std::process::id()));
+    let data_dir = dir.join("data");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(data_dir.join("encryption_key.bin"), KEY).unwrap();
+
+    let log = AuditLog::open(data_dir.join("audit.log")).unwrap().with_sensitive_ops(true);
+    for (key, value) in [("db", "first"), ("api", "token"), ("db", "second")] {
+        let (iv, encrypted_value) = encrypt_data(&KEY, value.as_bytes()).unwrap();
+        log.record_write(Some("alice"), key, value.as_bytes(), &iv, &encrypted_value).unwrap();
+    }
+    log.record_key(Some("alice"), DELETE_ACTION, "api", true, None).unwrap();
+    // without --log-sensitive-ops the key name is lost
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"hidden").unwrap();
+    AuditLog::open(data_dir.join("audit.log")).unwrap().record_write(None, "hidden", b"hidden", &iv, &encrypted_value).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_barn")).args(["restore", "data/audit.log", "restored.bin"]).current_dir(&dir).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Replayed 4 changes"));
+    assert!(stdout.contains("Skipped 1 changes"));
+
+    let store = KVStore::with_data_dir(&data_dir);
+    store.restore(dir.join("restored.bin").to_str().unwrap(), &KEY).await.unwrap();
+    assert_eq!(store.list_keys().await, ["db"]);
+    let secret = store.get_secret("db").await.unwrap();
+    assert_eq!(&decrypt_data(&KEY, &secret.iv, &secret.encrypted_value).unwrap()[..], b"second");
+
+    // an existing file is never overwritten
+    let again = Command::new(env!("CARGO_BIN_EXE_barn")).args(["restore", "data/audit.log", "restored.bin"]).current_dir(&dir).output().unwrap();
+    assert!(!again.status.success());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, KVStore};
+
+#[tokio::test]
+async fn values_survive_two_rotations() {
+    let dir = std::env::temp_dir().join(format!("barn-rotate-key-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+    let keys = [[1u8; 32], [2u8; 32], [3u8; 32]];
+
+    let store = KVStore::with_data_dir(&dir);
+    for (name, value) in [("a", "alpha"), ("b", "beta")] {
+        let (iv, encrypted_value) = encrypt_data(&keys[0], value.as_bytes()).unwrap();
+        store.set_secret(name.to_string(), iv, encrypted_value).await.unwrap();
+    }
+    store.persist(path, &keys[0]).await.unwrap();
+    let (original_iv, _) = store.load_from_file_encrypted(path).await.unwrap();
+
+    store.rotate_master_key(&keys[0], &keys[1], path).await.unwrap();
+    store.rotate_master_key(&keys[1], &keys[2], path).await.unwrap();
+    assert_ne!(store.load_from_file_encrypted(path).await.unwrap().0, original_iv);
+    assert!(store.restore(path, &keys[1]).await.is_err());
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &keys[2]).await.unwrap();
+    for (name, value) in [("a", "alpha"), ("b", "beta")] {
+        let secret = restored.get_secret(name).await.unwrap();
+        assert_eq!(*decrypt_data(&keys[2], &secret.iv, &secret.encrypted_value).unwrap(), value.as_bytes());
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn checkpoints_rotate_like_the_secrets_file() {
+    let dir = std::env::temp_dir().join(format!("barn-rotate-checkpoint-{}", std::process::id()));
+    let (old_key, new_key) = ([1u8; 32], [2u8; 32]);
+
+    let store = KVStore::with_data_dir(&dir);
+    let (iv, encrypted_value) = encrypt_data(&old_key, b"alpha").unwrap();
+    store.set_secret("a".to_string(), iv, encrypted_value).await.unwrap();
+    store.checkpoint("before-rotation", &old_key).await.unwrap();
+
+    let checkpoint = std::fs::read_dir(dir.join("checkpoints")).unwrap().next().unwrap().unwrap().path();
+    let checkpoint = checkpoint.to_str().unwrap();
+    KVStore::new().rotate_master_key(&old_key, &new_key, checkpoint).await.unwrap();
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore_checkpoint(checkpoint, &new_key).await.unwrap();
+    let secret = restored.get_secret("a").await.unwrap();
+    assert_eq!(*decrypt_data(&new_key, &secret.iv, &secret.encrypted_value).unwrap(), b"alpha");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
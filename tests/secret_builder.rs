@@ -0,0 +1,37 @@
+use barn::kv_silo::{decrypt_data, BuildError, SecretBuilder, DEFAULT_MAX_KEY_LENGTH};
+use std::time::Duration;
+
+const KEY: [u8; 32] = [12; 32];
+
+#[test]
+fn builds_an_encrypted_secret_with_its_metadata() {
+    let (key, secret) = SecretBuilder::new("db".to_string())
+        .value("hunter2")
+        .ttl(Duration::from_secs(60))
+        .tags(vec!["prod".to_string(), "pg".to_string(), "prod".to_string()])
+        .description("primary database")
+        .build(&KEY)
+        .unwrap();
+
+    assert_eq!(key, "db");
+    assert_eq!(&*decrypt_data(&KEY, &secret.iv, &secret.encrypted_value).unwrap(), b"hunter2");
+    assert_eq!(secret.metadata.tags, ["prod", "pg"]);
+    assert_eq!(secret.metadata.description.as_deref(), Some("primary database"));
+    assert_eq!(secret.expires_at, Some(secret.metadata.created_at + 60));
+    assert!(!secret.disabled);
+}
+
+#[test]
+fn rejects_what_a_store_would_not_accept() {
+    let build = |builder: SecretBuilder| builder.build(&KEY).err().unwrap();
+
+    assert!(matches!(build(SecretBuilder::new("db".to_string())), BuildError::MissingValue));
+    assert!(matches!(build(SecretBuilder::new(String::new()).value("x")), BuildError::EmptyKey));
+    assert!(matches!(
+        build(SecretBuilder::new("k".repeat(DEFAULT_MAX_KEY_LENGTH + 1)).value("x")),
+        BuildError::KeyTooLong { .. }
+    ));
+    assert!(matches!(build(SecretBuilder::new("db".to_string()).value("x").ttl(Duration::from_millis(500))), BuildError::ZeroTtl));
+    assert!(matches!(build(SecretBuilder::new("db".to_string()).value("x").tags(vec![String::new()])), BuildError::EmptyTag));
+    assert!(matches!(SecretBuilder::new("db".to_string()).value("x").build(&[0; 16]).err().unwrap(), BuildError::Crypto(_)));
+}
@@ -0,0 +1,67 @@
+use barn::kv_silo::KVStore;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const KEY: [u8; 32] = [7; 32];
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[tokio::test]
+async fn expired_secrets_read_as_absent_and_are_swept() {
+    let store = KVStore::new();
+    store.set_secret_expiring("old".to_string(), vec![0; 24], vec![1], Some(now() - 1)).await.unwrap();
+    store.set_secret_expiring("older".to_string(), vec![0; 24], vec![1], Some(now() - 10)).await.unwrap();
+    store.set_secret_expiring("fresh".to_string(), vec![0; 24], vec![1], Some(now() + 3600)).await.unwrap();
+
+    assert!(store.get_secret("old").await.is_none());
+    assert!(store.get_secret("fresh").await.is_some());
+    assert_eq!(store.info().await.total_secrets, 2);
+    assert_eq!(store.sweep_expired().await, 1);
+    assert_eq!(store.info().await.total_secrets, 1);
+}
+
+#[tokio::test]
+async fn expiry_survives_persist_and_restore() {
+    let dir = std::env::temp_dir().join(format!("barn-secret-expiry-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let expires_at = now() + 3600;
+    let store = KVStore::with_data_dir(&dir);
+    store.set_secret_expiring("a".to_string(), vec![0; 24], vec![1], Some(expires_at)).await.unwrap();
+    store.persist(path, &KEY).await.unwrap();
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &KEY).await.unwrap();
+    assert_eq!(restored.get_secret("a").await.unwrap().expires_at, Some(expires_at));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn expiry_takes_the_history_with_it() {
+    let store = KVStore::new();
+    store.set_secret("read".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    store.set_secret_expiring("read".to_string(), vec![0; 24], vec![2], Some(now() - 1)).await.unwrap();
+    store.set_secret("swept".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    store.set_secret_expiring("swept".to_string(), vec![0; 24], vec![2], Some(now() - 1)).await.unwrap();
+    assert!(store.get_secret_version("read", 1).await.is_none());
+    assert!(store.list_versions("swept").await.is_empty());
+
+    // overwriting an expired value that was never read starts a fresh history as well
+    store.set_secret("overwritten".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    store.set_secret_expiring("overwritten".to_string(), vec![0; 24], vec![2], Some(now() - 1)).await.unwrap();
+    store.set_secret("overwritten".to_string(), vec![0; 24], vec![3]).await.unwrap();
+    assert_eq!(store.list_versions("overwritten").await.len(), 1);
+
+    assert!(store.get_secret("read").await.is_none());
+    assert_eq!(store.sweep_expired().await, 1);
+
+    // a key set again starts without the expired value's versions
+    for key in ["read", "swept"] {
+        store.set_secret(key.to_string(), vec![0; 24], vec![3]).await.unwrap();
+        assert_eq!(store.list_versions(key).await.len(), 1, "{}", key);
+    }
+}
@@ -0,0 +1,91 @@
+use barn::kv_silo::{KVStore, SecretWrite};
+
+const KEY: [u8; 32] = [8; 32];
+
+#[tokio::test]
+async fn set_secret_tells_created_from_updated() {
+    let store = KVStore::new();
+    assert_eq!(store.set_secret("api".to_string(), vec![0; 24], vec![1]).await.unwrap(), SecretWrite::Created);
+    let created = store.get_metadata("api").await.unwrap();
+    assert!(created.created_at > 0);
+
+    assert_eq!(store.set_secret("api".to_string(), vec![1; 24], vec![2]).await.unwrap(), SecretWrite::Updated);
+    let updated = store.get_metadata("api").await.unwrap();
+    assert_eq!(updated.created_at, created.created_at);
+    assert!(updated.updated_at >= created.updated_at);
+}
+
+#[tokio::test]
+async fn metadata_survives_overwrites_and_persistence() {
+    let dir = std::env::temp_dir().join(format!("barn-metadata-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    store.set_secret("db".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    store.set_secret("cache".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    assert!(store.set_metadata("db", Some("primary database".to_string()), vec!["prod".to_string()]).await);
+    assert!(store.set_metadata("cache", None, vec!["prod".to_string(), "redis".to_string()]).await);
+    assert!(!store.set_metadata("missing", None, Vec::new()).await);
+
+    // a new value keeps the description and tags
+    store.set_secret("db".to_string(), vec![1; 24], vec![2]).await.unwrap();
+    store.persist(path, &KEY).await.unwrap();
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &KEY).await.unwrap();
+    assert_eq!(restored.get_metadata("db").await.unwrap().description.as_deref(), Some("primary database"));
+    assert_eq!(restored.find_by_tag("prod").await, ["cache", "db"]);
+    assert_eq!(restored.find_by_tag("redis").await, ["cache"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn tags_change_without_touching_the_value() {
+    let store = KVStore::new();
+    store.set_secret("db".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    let before = store.get_secret("db").await.unwrap();
+
+    assert_eq!(store.add_tag("db", "prod").await.unwrap(), ["prod"]);
+    assert_eq!(store.add_tag("db", "prod").await.unwrap(), ["prod"]);
+    assert_eq!(store.add_tag("db", "pg").await.unwrap(), ["prod", "pg"]);
+    assert_eq!(store.remove_tag("db", "prod").await.unwrap(), ["pg"]);
+    assert!(store.add_tag("missing", "prod").await.is_none());
+
+    let after = store.get_secret("db").await.unwrap();
+    assert_eq!((after.iv, after.encrypted_value), (before.iv, before.encrypted_value));
+    assert_eq!(store.list_versions("db").await.len(), 1);
+}
+
+#[tokio::test]
+async fn count_by_tag_puts_the_most_used_tag_first() {
+    let store = KVStore::new();
+    for (key, tags) in [("db", vec!["prod", "pg"]), ("cache", vec!["prod"]), ("old", vec!["prod", "pg"]), ("ci", vec!["dev"])] {
+        store.set_secret(key.to_string(), vec![0; 24], vec![1]).await.unwrap();
+        store.set_metadata(key, None, tags.into_iter().map(String::from).collect()).await;
+    }
+    store.disable_secret("old").await;
+
+    let counts = store.count_by_tag().await;
+    assert_eq!(counts, [("prod".to_string(), 2), ("dev".to_string(), 1), ("pg".to_string(), 1)]);
+}
+
+#[tokio::test]
+async fn store_info_reports_oldest_and_newest_secret() {
+    let store = KVStore::new();
+    let info = store.info().await;
+    assert_eq!((info.oldest_secret, info.newest_secret), (None, None));
+
+    store.set_secret("a".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    store.set_secret("b".to_string(), vec![0; 24], vec![1]).await.unwrap();
+    let created: Vec<u64> = [store.get_metadata("a").await, store.get_metadata("b").await]
+        .into_iter()
+        .map(|metadata| metadata.unwrap().created_at)
+        .collect();
+
+    let info = store.info().await;
+    assert_eq!(info.oldest_secret, created.iter().min().copied());
+    assert_eq!(info.newest_secret, created.iter().max().copied());
+}
@@ -0,0 +1,52 @@
+use barn::kv_silo::KVStore;
+
+const KEY: [u8; 32] = [3; 32];
+
+async fn set(store: &KVStore, value: u8) {
+    store.set_secret("db".to_string(), vec![value; 24], vec![value]).await.unwrap();
+}
+
+#[tokio::test]
+async fn oldest_version_is_dropped_past_the_bound() {
+    let store = KVStore::new().with_max_versions(3);
+    for value in 1..=4 {
+        set(&store, value).await;
+    }
+
+    let versions: Vec<Vec<u8>> = store.list_versions("db").await.into_iter().map(|secret| secret.encrypted_value).collect();
+    assert_eq!(versions, [[4], [3], [2]]);
+    assert_eq!(store.get_secret_version("db", 2).await.unwrap().encrypted_value, [2]);
+    assert!(store.get_secret_version("db", 3).await.is_none());
+}
+
+#[tokio::test]
+async fn rollback_promotes_the_previous_version() {
+    let store = KVStore::new();
+    set(&store, 1).await;
+    assert!(!store.rollback("db").await);
+
+    set(&store, 2).await;
+    assert!(store.rollback("db").await);
+    assert_eq!(store.get_secret("db").await.unwrap().encrypted_value, [1]);
+    assert_eq!(store.list_versions("db").await.len(), 1);
+}
+
+#[tokio::test]
+async fn history_survives_persist_and_restore() {
+    let dir = std::env::temp_dir().join(format!("barn-versions-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("secrets.bin");
+    let path = path.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&dir);
+    set(&store, 1).await;
+    set(&store, 2).await;
+    store.persist(path, &KEY).await.unwrap();
+
+    let restored = KVStore::with_data_dir(&dir);
+    restored.restore(path, &KEY).await.unwrap();
+    assert_eq!(restored.get_secret_version("db", 1).await.unwrap().encrypted_value, [1]);
+    assert!(restored.rollback("db").await);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
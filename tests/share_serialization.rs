@@ -0,0 +1,42 @@
+use barn::kv_silo::{reconstruct_dek, split_dek, ShareSerialization};
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use sharks::Share;
+
+#[test]
+fn shares_round_trip_through_bytes() {
+    for _ in 0..200 {
+        let mut dek = vec![0u8; 32];
+        OsRng.fill_bytes(&mut dek);
+        let total = OsRng.gen_range(2..=12);
+        let threshold = OsRng.gen_range(2..=total);
+
+        let shares = split_dek(&dek, threshold, total).unwrap();
+        let reloaded: Vec<Share> = shares
+            .iter()
+            .map(|share| {
+                let bytes = share.to_bytes();
+                let restored = Share::from_bytes(&bytes).unwrap();
+                assert_eq!(restored.to_bytes(), bytes);
+                restored
+            })
+            .collect();
+
+        assert_eq!(*reconstruct_dek(&reloaded[..threshold as usize], threshold).unwrap(), dek);
+    }
+}
+
+#[test]
+fn truncated_shares_are_rejected() {
+    assert!(Share::from_bytes(&[]).is_err());
+    assert!(Share::from_bytes(&[1]).is_err());
+    assert!(Share::from_bytes(&[0, 1, 2]).is_err());
+}
+
+#[test]
+fn shares_of_different_lengths_do_not_reconstruct() {
+    let shares = split_dek(&[7u8; 32], 2, 3).unwrap();
+    let short = Share::from_bytes(&shares[1].to_bytes()[..10]).unwrap();
+
+    assert!(reconstruct_dek(&[shares[0].clone(), short], 2).is_err());
+}
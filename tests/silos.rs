@@ -0,0 +1,58 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, validate_silo_name, SiloManager};
+
+const KEY: [u8; 32] = [9; 32];
+
+#[test]
+fn silo_names_cannot_escape_the_silos_directory() {
+    for name in ["", "..", "../etc", "a/b", "a\\b", "a..b", "with space"] {
+        assert!(validate_silo_name(name).is_err(), "{:?} was accepted", name);
+    }
+    for name in ["app1", "billing-prod", "team_a.v2"] {
+        assert!(validate_silo_name(name).is_ok(), "{:?} was rejected", name);
+    }
+}
+
+#[tokio::test]
+async fn silos_have_separate_keyspaces_and_files() {
+    let dir = std::env::temp_dir().join(format!("barn-silos-{}", std::process::id()));
+    let silos = SiloManager::new(&dir);
+
+    for (name, value) in [("app1", vec![1]), ("app2", vec![2])] {
+        let silo = silos.get_or_create_silo(name, &KEY).await.unwrap();
+        silo.set_secret("db".to_string(), vec![0; 24], value).await.unwrap();
+        silos.persist_silo(name, &KEY).await.unwrap();
+    }
+    assert!(silos.get_silo("app3", &KEY).await.unwrap().is_none());
+
+    let reloaded = SiloManager::new(&dir);
+    assert_eq!(reloaded.list_silos().await.unwrap(), ["app1", "app2"]);
+    let app2 = reloaded.get_silo("app2", &KEY).await.unwrap().unwrap();
+    assert_eq!(app2.get_secret("db").await.unwrap().encrypted_value, [2]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn cloned_silo_holds_the_same_values() {
+    let dir = std::env::temp_dir().join(format!("barn-silo-clone-{}", std::process::id()));
+    let silos = SiloManager::new(&dir);
+    let source = silos.get_or_create_silo("app1", &KEY).await.unwrap();
+    let (iv, encrypted_value) = encrypt_data(&KEY, b"hunter2").unwrap();
+    source.set_secret("db".to_string(), iv, encrypted_value).await.unwrap();
+    silos.persist_silo("app1", &KEY).await.unwrap();
+
+    assert_eq!(silos.clone_silo("app1", "copy", &KEY, false).await.unwrap(), 1);
+    assert_eq!(silos.clone_silo("app1", "fresh", &KEY, true).await.unwrap(), 1);
+    assert!(silos.clone_silo("app1", "copy", &KEY, false).await.is_err());
+    assert!(silos.clone_silo("missing", "other", &KEY, false).await.is_err());
+
+    let reloaded = SiloManager::new(&dir);
+    let original = reloaded.get_silo("app1", &KEY).await.unwrap().unwrap().get_secret("db").await.unwrap();
+    let copy = reloaded.get_silo("copy", &KEY).await.unwrap().unwrap().get_secret("db").await.unwrap();
+    let fresh = reloaded.get_silo("fresh", &KEY).await.unwrap().unwrap().get_secret("db").await.unwrap();
+    assert_eq!(copy.encrypted_value, original.encrypted_value);
+    assert_ne!(fresh.iv, original.iv);
+    assert_eq!(&decrypt_data(&KEY, &fresh.iv, &fresh.encrypted_value).unwrap()[..], b"hunter2");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
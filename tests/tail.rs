@@ -0,0 +1,58 @@
+use barn::kv_silo::{encrypt_data, KVStore};
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+const KEY: [u8; 32] = [11; 32];
+
+async fn put(store: &KVStore, key: &str, value: &str) {
+    let (iv, encrypted_value) = encrypt_data(&KEY, value.as_bytes()).unwrap();
+    store.set_secret(key.to_string(), iv, encrypted_value).await.unwrap();
+}
+
+// runs the real binary, which reads data/encryption_key.bin from its working directory
+#[tokio::test]
+async fn tail_prints_only_the_keys_a_save_changed() {
+    let dir = std::env::temp_dir().join(format!("barn-tail-{}", std::process::id()));
+    let data_dir = dir.join("data");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(data_dir.join("encryption_key.bin"), KEY).unwrap();
+    let secrets_file = data_dir.join("secrets.bin");
+    let secrets_file = secrets_file.to_str().unwrap();
+
+    let store = KVStore::with_data_dir(&data_dir);
+    put(&store, "db", "hunter2").await;
+    put(&store, "api", "abc123").await;
+    store.persist(secrets_file, &KEY).await.unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_barn"))
+        .arg("tail")
+        .current_dir(&dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (lines, received) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = lines.send(line.unwrap());
+        }
+    });
+    // give the watcher time to start before the file changes
+    std::thread::sleep(Duration::from_millis(500));
+
+    put(&store, "db", "correct horse").await;
+    store.delete_secret("api").await;
+    put(&store, "cache", "redis").await;
+    store.persist(secrets_file, &KEY).await.unwrap();
+
+    let mut changed: Vec<String> = (0..3).map(|_| received.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+    changed.sort();
+    assert_eq!(changed, ["api", "cache", "db"]);
+    assert!(received.recv_timeout(Duration::from_millis(300)).is_err());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
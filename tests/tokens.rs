@@ -0,0 +1,72 @@
+use barn::tokens::TokenStore;
+
+const KEY: [u8; 32] = [5; 32];
+
+fn store(name: &str) -> (TokenStore, std::path::PathBuf) {
+    let path = std::env::temp_dir().join(format!("barn-tokens-{}-{}.json", name, std::process::id()));
+    (TokenStore::new(&path), path)
+}
+
+#[tokio::test]
+async fn token_is_used_up_after_max_uses() {
+    let (tokens, path) = store("uses");
+    let token = tokens.issue("db", 60, 2, &KEY).await.unwrap();
+
+    assert!(tokens.redeem("db", &token, &KEY).await.unwrap());
+    assert!(tokens.redeem("db", &token, &KEY).await.unwrap());
+    assert!(!tokens.redeem("db", &token, &KEY).await.unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn token_only_reads_its_own_key() {
+    let (tokens, path) = store("key");
+    let token = tokens.issue("db", 60, 1, &KEY).await.unwrap();
+
+    assert!(!tokens.redeem("api", &token, &KEY).await.unwrap());
+    assert!(!tokens.redeem("db", &token, &[6; 32]).await.unwrap());
+    assert!(tokens.redeem("db", &token, &KEY).await.unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn expired_or_edited_tokens_are_rejected() {
+    let (tokens, path) = store("expired");
+    let expired = tokens.issue("db", 0, 1, &KEY).await.unwrap();
+    assert!(!tokens.redeem("db", &expired, &KEY).await.unwrap());
+
+    // pushing the expiry out breaks the signature
+    let token = tokens.issue("db", 60, 1, &KEY).await.unwrap();
+    let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+    parts[1] = (parts[1].parse::<u64>().unwrap() + 3600).to_string();
+    assert!(!tokens.redeem("db", &parts.join("."), &KEY).await.unwrap());
+    assert!(!tokens.redeem("db", "not-a-token", &KEY).await.unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn rotation_keeps_the_file_readable_and_revokes_old_tokens() {
+    let (tokens, path) = store("rotate");
+    let new_key = [6; 32];
+    let old_token = tokens.issue("db", 60, 1, &KEY).await.unwrap();
+
+    assert_eq!(tokens.rotate_master_key(&KEY, &new_key).await.unwrap(), 1);
+    assert!(!tokens.redeem("db", &old_token, &new_key).await.unwrap());
+
+    let token = tokens.issue("db", 60, 1, &new_key).await.unwrap();
+    assert!(tokens.redeem("db", &token, &new_key).await.unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn checking_a_token_does_not_use_it_up() {
+    let (tokens, path) = store("check");
+    let token = tokens.issue("db", 60, 1, &KEY).await.unwrap();
+
+    assert!(tokens.is_valid("db", &token, &KEY).await.unwrap());
+    assert!(tokens.is_valid("db", &token, &KEY).await.unwrap());
+    assert!(!tokens.is_valid("api", &token, &KEY).await.unwrap());
+    assert!(tokens.redeem("db", &token, &KEY).await.unwrap());
+    assert!(!tokens.is_valid("db", &token, &KEY).await.unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,20 @@
+use barn::users::{load_bcrypt_cost, save_bcrypt_cost, tune_bcrypt_cost};
+use std::time::Duration;
+
+#[test]
+fn tuning_never_goes_below_the_minimum_cost() {
+    assert_eq!(tune_bcrypt_cost(Duration::ZERO).unwrap(), 10);
+}
+
+#[test]
+fn saved_cost_is_read_back_and_defaults_to_bcrypts() {
+    let path = std::env::temp_dir().join(format!("barn-bcrypt-cost-{}", std::process::id()));
+    assert_eq!(load_bcrypt_cost(&path).unwrap(), bcrypt::DEFAULT_COST);
+
+    save_bcrypt_cost(&path, 13).unwrap();
+    assert_eq!(load_bcrypt_cost(&path).unwrap(), 13);
+
+    std::fs::write(&path, "thirteen").unwrap();
+    assert!(load_bcrypt_cost(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
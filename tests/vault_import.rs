@@ -0,0 +1,42 @@
+use barn::vault::{parse_kv_get, KvVersion, VaultImportError};
+
+fn fields(json: &str, path: &str) -> Vec<(String, String)> {
+    parse_kv_get(json.as_bytes(), path)
+        .unwrap()
+        .fields
+        .iter()
+        .map(|(key, value)| (key.clone(), String::from_utf8(value.to_vec()).unwrap()))
+        .collect()
+}
+
+#[test]
+fn kv_v2_fields_come_from_data_data() {
+    let json = r#"{
+        "request_id": "3d2b", "lease_id": "", "lease_duration": 0, "renewable": false,
+        "data": {
+            "data": {"password": "hunter2", "port": 5432},
+            "metadata": {"created_time": "2024-01-01T00:00:00Z", "version": 3}
+        },
+        "warnings": null
+    }"#;
+    let parsed = parse_kv_get(json.as_bytes(), "db/").unwrap();
+    assert_eq!(parsed.version, KvVersion::V2);
+    assert_eq!(parsed.lease_duration, None);
+    assert_eq!(fields(json, "db/"), [("db/password".to_string(), "hunter2".to_string()), ("db/port".to_string(), "5432".to_string())]);
+}
+
+#[test]
+fn kv_v1_fields_come_from_data_with_the_lease_as_ttl() {
+    let json = r#"{"lease_duration": 2764800, "data": {"token": "abc123"}}"#;
+    let parsed = parse_kv_get(json.as_bytes(), "ci").unwrap();
+    assert_eq!(parsed.version, KvVersion::V1);
+    assert_eq!(parsed.lease_duration, Some(2764800));
+    assert_eq!(fields(json, "ci"), [("ci/token".to_string(), "abc123".to_string())]);
+}
+
+#[test]
+fn output_without_data_is_rejected() {
+    assert!(matches!(parse_kv_get(br#"{"errors": ["permission denied"]}"#, "db").err().unwrap(), VaultImportError::MissingData));
+    assert!(matches!(parse_kv_get(br#"{"data": {"metadata": {}}}"#, "db").err().unwrap(), VaultImportError::MissingData));
+    assert!(matches!(parse_kv_get(b"not json", "db").err().unwrap(), VaultImportError::InvalidJson(_)));
+}
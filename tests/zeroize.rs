@@ -0,0 +1,51 @@
+use barn::kv_silo::{decrypt_data, encrypt_data, get_or_create_key, reconstruct_dek, split_dek};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use zeroize::{Zeroize, Zeroizing};
+
+const KEY: [u8; 32] = [4; 32];
+
+// records on drop whether the buffer it held had been wiped, capacity included
+struct Probe {
+    bytes: Vec<u8>,
+    scrubbed: Arc<AtomicBool>,
+}
+
+impl Zeroize for Probe {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        // Vec::zeroize writes zeros over the whole allocation before clearing it
+        let spare = self.bytes.spare_capacity_mut();
+        let wiped = spare.iter().all(|byte| unsafe { byte.assume_init() } == 0);
+        self.scrubbed.store(self.bytes.is_empty() && wiped, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn decrypted_plaintext_is_wiped_on_drop() {
+    let (iv, ciphertext) = encrypt_data(&KEY, b"correct horse battery staple").unwrap();
+    let mut plaintext: Zeroizing<Vec<u8>> = decrypt_data(&KEY, &iv, &ciphertext).unwrap();
+    assert_eq!(&plaintext[..], b"correct horse battery staple");
+
+    let scrubbed = Arc::new(AtomicBool::new(false));
+    let probe = Zeroizing::new(Probe { bytes: std::mem::take(&mut *plaintext), scrubbed: scrubbed.clone() });
+    drop(probe);
+    assert!(scrubbed.load(Ordering::SeqCst));
+}
+
+#[test]
+fn key_material_is_zeroizing() {
+    let shares = split_dek(&KEY, 2, 3).unwrap();
+    let dek: Zeroizing<Vec<u8>> = reconstruct_dek(&shares[..2], 2).unwrap();
+    assert_eq!(&dek[..], &KEY);
+
+    let path = std::env::temp_dir().join(format!("barn-zeroize-key-{}", std::process::id()));
+    let key: Zeroizing<Vec<u8>> = get_or_create_key(path.to_str().unwrap()).unwrap();
+    assert_eq!(key.len(), 32);
+    std::fs::remove_file(&path).unwrap();
+}
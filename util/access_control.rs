@@ -1,18 +1,196 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::audit::{self, AuditLog};
+use crate::kv_silo::write_atomically;
+
+// names the v5 UUIDs user_id derives; changing it would orphan every grant
+const USER_ID_NAMESPACE: Uuid = Uuid::from_u128(0x6f1b_2d4e_8a3c_4b5f_9e7d_1c2a_3b4d_5e6f);
+
+// users are stored by name, so grants are keyed by a UUID derived from it; /login reports it
+pub fn user_id(username: &str) -> Uuid {
+    Uuid::new_v5(&USER_ID_NAMESPACE, username.as_bytes())
+}
+
+// ordered so a higher level includes everything below it: Admin > Write > Read
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    Write,
+    Admin,
+}
+
 pub struct AccessControl {
-    users: HashMap<Uuid, Vec<String>>, // Maps user IDs to a list of accessible paths
+    users: HashMap<Uuid, HashMap<String, Permission>>, // Maps user IDs to the level granted on each path
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl Default for AccessControl {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AccessControl {
     pub fn new() -> Self {
         AccessControl {
             users: HashMap::new(),
+            audit: None,
         }
     }
 
-    pub fn grant_access(&mut self, user_id: Uuid, path: String) {
-        self.users.entry(user_id).or_insert(Vec::new()).push(path);
+    // grants written by save; a missing file is an empty table
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let users = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(AccessControl { users, audit: None })
+    }
+
+    // grants name paths and levels but hold no secret material, so the file is plain JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_atomically(path, &serde_json::to_vec_pretty(&self.users)?)
     }
-}
\ No newline at end of file
+
+    // every has_access decision is recorded to the log from here on; the log is shared so its
+    // hash chain stays linear alongside the server's own entries
+    pub fn with_audit_log(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    // replaces any earlier grant on the same path, so this can also lower a user's level
+    pub fn grant_access(&mut self, user_id: Uuid, path: String, permission: Permission, by: Option<&str>) -> io::Result<()> {
+        self.record_change(by, user_id, &path, Some(permission))?;
+        self.users.entry(user_id).or_default().insert(path, permission);
+        Ok(())
+    }
+
+    // fails closed: a decision that cannot be audited is a denial
+    pub fn has_access(&self, user_id: Uuid, path: &str, required: Permission) -> bool {
+        let allowed = self
+            .users
+            .get(&user_id)
+            .and_then(|paths| paths.get(path))
+            .is_some_and(|granted| *granted >= required);
+
+        match &self.audit {
+            Some(audit) => {
+                let action = format!("access:{:?}", required).to_lowercase();
+                audit.record_key(Some(&user_id.to_string()), &action, path, allowed, None).is_ok() && allowed
+            }
+            None => allowed,
+        }
+    }
+
+    // grants permission on each path the user has no grant on yet, leaving existing grants at
+    // their level; returns how many were added
+    pub fn bulk_grant(&mut self, user_id: Uuid, paths: &[&str], permission: Permission, by: Option<&str>) -> io::Result<usize> {
+        let mut added = 0;
+        for path in paths {
+            if self.users.get(&user_id).is_some_and(|granted| granted.contains_key(*path)) {
+                continue;
+            }
+            self.grant_access(user_id, path.to_string(), permission, by)?;
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    // returns how many of the paths had a grant to remove
+    pub fn bulk_revoke(&mut self, user_id: Uuid, paths: &[&str], by: Option<&str>) -> io::Result<usize> {
+        let mut revoked = 0;
+        for path in paths {
+            if self.revoke_access(user_id, path, by)? {
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+
+    // gives every grant on `from` or a path under `from/` a twin under `to`, as when a context is
+    // cloned; existing grants on the new paths are kept, and the count copied is returned
+    pub fn copy_grants(&mut self, from: &str, to: &str, by: Option<&str>) -> io::Result<usize> {
+        let mut renamed = Vec::new();
+        for (user_id, paths) in &self.users {
+            for (path, permission) in paths {
+                let rest = match path.strip_prefix(from) {
+                    Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+                    _ => continue,
+                };
+                let path = format!("{}{}", to, rest);
+                if !paths.contains_key(&path) {
+                    renamed.push((*user_id, path, *permission));
+                }
+            }
+        }
+        for (user_id, path, permission) in &renamed {
+            self.grant_access(*user_id, path.clone(), *permission, by)?;
+        }
+        Ok(renamed.len())
+    }
+
+    // returns whether there was a grant to remove
+    pub fn revoke_access(&mut self, user_id: Uuid, path: &str, by: Option<&str>) -> io::Result<bool> {
+        if !self.users.get(&user_id).is_some_and(|paths| paths.contains_key(path)) {
+            return Ok(false);
+        }
+        self.record_change(by, user_id, path, None)?;
+        if let Some(paths) = self.users.get_mut(&user_id) {
+            paths.remove(path);
+            if paths.is_empty() {
+                self.users.remove(&user_id);
+            }
+        }
+        Ok(true)
+    }
+
+    // a change that cannot be audited is not made; None is a revoke
+    fn record_change(&self, by: Option<&str>, user_id: Uuid, path: &str, permission: Option<Permission>) -> io::Result<()> {
+        let audit = match &self.audit {
+            Some(audit) => audit,
+            None => return Ok(()),
+        };
+        let action = match permission {
+            Some(permission) => format!("grant:{:?}", permission).to_lowercase(),
+            None => "revoke".to_string(),
+        };
+        audit.record_grant_change(by, &user_id.to_string(), &action, path)
+    }
+}
+
+// one grant or revoke read back from the audit log
+pub struct GrantChange {
+    pub at: u64,
+    pub by: Option<String>,
+    pub user_id: String,
+    pub path: String,
+    // None for a revoke
+    pub permission: Option<Permission>,
+}
+
+// the grant changes in an audit log, oldest first; replaying them in order rebuilds the grants
+// made while the log was attached
+pub fn grant_history(audit_path: impl Into<PathBuf>) -> io::Result<Vec<GrantChange>> {
+    let mut changes = Vec::new();
+    for entry in audit::entries(audit_path)? {
+        let user_id = match entry.subject {
+            Some(user_id) => user_id,
+            None => continue,
+        };
+        let permission = match entry.action.strip_prefix("grant:") {
+            Some(level) => Some(serde_json::from_value(serde_json::Value::String(level.to_string()))?),
+            None if entry.action == "revoke" => None,
+            None => continue,
+        };
+        changes.push(GrantChange { at: entry.timestamp, by: entry.user_id, user_id, path: entry.resource, permission });
+    }
+    Ok(changes)
+}
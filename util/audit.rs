@@ -0,0 +1,293 @@
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::{base64, hex};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// prev_hash of the first entry in a log
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// the first entry of a log started by --truncate-log-at
+const TRUNCATED_ACTION: &str = "log_truncated";
+// the actions barn restore replays
+pub const WRITE_ACTION: &str = "write";
+pub const DELETE_ACTION: &str = "delete";
+// how a key name is written without --log-sensitive-ops
+pub const HASHED_KEY_PREFIX: &str = "sha256:";
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub user_id: Option<String>,
+    pub action: String,
+    pub resource: String,
+    pub allowed: bool,
+    // the user a grant change was made for; user_id is then whoever made it. Left out of the JSON
+    // when unset, so entries written before it existed still hash the same
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    // SHA-256 of the plaintext a store wrote, so a changed value shows without the value itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_sha256: Option<String>,
+    // base64 of the nonce followed by the ciphertext a /secret write stored, still under the master key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ciphertext: Option<String>,
+    pub prev_hash: String,
+}
+
+// one line of the log: the entry plus the SHA-256 of the entry's JSON, which the next entry chains in
+#[derive(Serialize, Deserialize)]
+struct AuditLine {
+    #[serde(flatten)]
+    entry: AuditEntry,
+    hash: String,
+}
+
+impl AuditEntry {
+    fn hash(&self) -> io::Result<String> {
+        Ok(hex::encode(digest(&SHA256, &serde_json::to_vec(self)?).as_ref()))
+    }
+}
+
+// appends newline-delimited JSON entries, each chained to the one before it
+pub struct AuditLog {
+    path: PathBuf,
+    // the hash of the last entry written, guarded so concurrent writers keep the chain linear
+    last_hash: Mutex<String>,
+    // once the file reaches this size it is moved to {path}.old before the next append
+    truncate_at: Option<u64>,
+    // key names are written as-is rather than hashed
+    sensitive_ops: bool,
+    // false for --no-audit: nothing is written and every record succeeds
+    enabled: bool,
+}
+
+impl AuditLog {
+    // picks the chain up from the last entry when the file already exists
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let last_hash = last_hash_in(&path)?.unwrap_or_else(|| GENESIS_HASH.to_string());
+        Ok(AuditLog {
+            path,
+            last_hash: Mutex::new(last_hash),
+            truncate_at: None,
+            sensitive_ops: false,
+            enabled: true,
+        })
+    }
+
+    // a log that records nothing
+    pub fn disabled() -> Self {
+        AuditLog {
+            path: PathBuf::new(),
+            last_hash: Mutex::new(GENESIS_HASH.to_string()),
+            truncate_at: None,
+            sensitive_ops: false,
+            enabled: false,
+        }
+    }
+
+    // the new file starts with a log_truncated entry chained to the last one in {path}.old, so
+    // verify can follow the chain across the move; an earlier .old is overwritten
+    pub fn with_truncate_at(mut self, max_bytes: Option<u64>) -> Self {
+        self.truncate_at = max_bytes;
+        self
+    }
+
+    // key names are hashed unless this is set; plaintext values are only ever logged as a hash
+    pub fn with_sensitive_ops(mut self, sensitive_ops: bool) -> Self {
+        self.sensitive_ops = sensitive_ops;
+        self
+    }
+
+    // for resources that are not secret keys, such as a login's "session"
+    pub fn record(&self, user_id: Option<&str>, action: &str, resource: &str, allowed: bool) -> io::Result<()> {
+        self.append(new_entry(user_id, action, resource, allowed))
+    }
+
+    // `value` is the plaintext being written, if any
+    pub fn record_key(&self, user_id: Option<&str>, action: &str, key: &str, allowed: bool, value: Option<&[u8]>) -> io::Result<()> {
+        let mut entry = new_entry(user_id, action, &self.key_name(key), allowed);
+        entry.value_sha256 = value.map(sha256_hex);
+        self.append(entry)
+    }
+
+    // a /secret write, carrying the stored nonce and ciphertext so barn restore can replay it
+    pub fn record_write(&self, user_id: Option<&str>, key: &str, value: &[u8], iv: &[u8], encrypted_value: &[u8]) -> io::Result<()> {
+        let mut entry = new_entry(user_id, WRITE_ACTION, &self.key_name(key), true);
+        entry.value_sha256 = Some(sha256_hex(value));
+        entry.ciphertext = Some(base64::encode([iv, encrypted_value].concat(), base64::Variant::Original));
+        self.append(entry)
+    }
+
+    // a grant made or revoked by `by` for the user `subject` on the key `resource`
+    pub fn record_grant_change(&self, by: Option<&str>, subject: &str, action: &str, resource: &str) -> io::Result<()> {
+        let mut entry = new_entry(by, action, &self.key_name(resource), true);
+        entry.subject = Some(subject.to_string());
+        self.append(entry)
+    }
+
+    fn key_name(&self, key: &str) -> String {
+        if self.sensitive_ops {
+            key.to_string()
+        } else {
+            format!("{}{}", HASHED_KEY_PREFIX, sha256_hex(key.as_bytes()))
+        }
+    }
+
+    // fills in the entry's timestamp and prev_hash
+    fn append(&self, mut entry: AuditEntry) -> io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let mut last_hash = self.last_hash.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Some(max_bytes) = self.truncate_at {
+            let size = match fs::metadata(&self.path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e),
+            };
+            if size > 0 && size >= max_bytes {
+                let old_path = old_log_path(&self.path);
+                fs::rename(&self.path, &old_path)?;
+                let mut marker = new_entry(None, TRUNCATED_ACTION, &old_path.display().to_string(), true);
+                marker.timestamp = timestamp;
+                marker.prev_hash = last_hash.clone();
+                *last_hash = self.write_line(marker)?;
+            }
+        }
+
+        entry.timestamp = timestamp;
+        entry.prev_hash = last_hash.clone();
+        *last_hash = self.write_line(entry)?;
+        Ok(())
+    }
+
+    // returns the entry's hash
+    fn write_line(&self, entry: AuditEntry) -> io::Result<String> {
+        let hash = entry.hash()?;
+        let mut line = serde_json::to_vec(&AuditLine { entry, hash: hash.clone() })?;
+        line.push(b'\n');
+        OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(&line)?;
+        Ok(hash)
+    }
+}
+
+fn new_entry(user_id: Option<&str>, action: &str, resource: &str, allowed: bool) -> AuditEntry {
+    AuditEntry {
+        timestamp: 0,
+        user_id: user_id.map(str::to_string),
+        action: action.to_string(),
+        resource: resource.to_string(),
+        allowed,
+        subject: None,
+        value_sha256: None,
+        ciphertext: None,
+        prev_hash: String::new(),
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(digest(&SHA256, bytes).as_ref())
+}
+
+fn old_log_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".old");
+    PathBuf::from(name)
+}
+
+// None for a missing or empty file
+fn last_hash_in(path: &Path) -> io::Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut last_hash = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.is_empty() {
+            last_hash = Some(serde_json::from_str::<AuditLine>(&line)?.hash);
+        }
+    }
+    Ok(last_hash)
+}
+
+#[derive(Debug)]
+pub struct BrokenLink {
+    // 1-based, as an editor would show it
+    pub line: usize,
+    pub reason: String,
+}
+
+// every entry in the log, oldest first, without checking the chain
+pub fn entries(path: impl Into<PathBuf>) -> io::Result<Vec<AuditEntry>> {
+    let file = File::open(path.into())?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.is_empty() {
+            entries.push(serde_json::from_str::<AuditLine>(&line)?.entry);
+        }
+    }
+    Ok(entries)
+}
+
+// entries() of the log and, when it was truncated, of the {path}.old before it
+pub fn entries_with_old(path: impl Into<PathBuf>) -> io::Result<Vec<AuditEntry>> {
+    let path = path.into();
+    let current = entries(&path)?;
+    if current.first().is_some_and(|entry| entry.action == TRUNCATED_ACTION) {
+        let mut all = entries(old_log_path(&path))?;
+        all.extend(current);
+        return Ok(all);
+    }
+    Ok(current)
+}
+
+// walks the whole chain; Ok(Err(..)) describes the first entry that does not check out
+pub fn verify(path: impl Into<PathBuf>) -> io::Result<Result<usize, BrokenLink>> {
+    let path = path.into();
+    let file = File::open(&path)?;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut entries = 0;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let broken = |reason: &str| Ok(Err(BrokenLink { line: index + 1, reason: reason.to_string() }));
+
+        let parsed: AuditLine = match serde_json::from_str(&line) {
+            Ok(parsed) => parsed,
+            Err(_) => return broken("not a valid audit entry"),
+        };
+        // a log that was truncated carries on from the last entry of {path}.old
+        if entries == 0 && parsed.entry.action == TRUNCATED_ACTION {
+            match last_hash_in(&old_log_path(&path))? {
+                Some(old_hash) => prev_hash = old_hash,
+                None => return broken("log was truncated but the .old file it continues is missing"),
+            }
+        }
+        if parsed.entry.prev_hash != prev_hash {
+            return broken("prev_hash does not match the previous entry");
+        }
+        if parsed.entry.hash()? != parsed.hash {
+            return broken("entry was modified after it was written");
+        }
+
+        prev_hash = parsed.hash;
+        entries += 1;
+    }
+
+    Ok(Ok(entries))
+}
@@ -1,77 +1,1524 @@
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
-use tokio::sync::RwLock;
-use std::io::{Read, Write};
-use std::fs::File;
-use chacha20poly1305::aead::{Aead, KeyInit};
-use chacha20poly1305::{XChaCha20Poly1305, Key, XNonce};
+use std::fmt;
+use tokio::sync::{Mutex, RwLock};
+use std::io::{self, Read, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::aead::{self, Aead, KeyInit};
+use chacha20poly1305::XChaCha20Poly1305;
 use rand::RngCore;
 use rand::rngs::OsRng;
+use sharks::{Share, Sharks};
+use tempfile::NamedTempFile;
+use zeroize::Zeroizing;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Secret {
     pub iv: Vec<u8>,
     pub encrypted_value: Vec<u8>,
+    #[serde(default)]
+    pub disabled: bool,
+    // unix timestamp after which the secret reads as absent
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub metadata: SecretMetadata,
+}
+
+// timestamps are unix seconds, and 0 for secrets stored before metadata was kept
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecretMetadata {
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+}
+
+// what set_secret did to the key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretWrite {
+    Created,
+    Updated,
+}
+
+impl Secret {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    EmptyKey,
+    KeyTooLong { len: usize, max: usize },
+    MissingValue,
+    ZeroTtl,
+    EmptyTag,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::EmptyKey => write!(f, "key must not be empty"),
+            BuildError::KeyTooLong { len, max } => write!(f, "key is {} bytes long, the limit is {}", len, max),
+            BuildError::MissingValue => write!(f, "no value was given"),
+            BuildError::ZeroTtl => write!(f, "ttl must be at least a second"),
+            BuildError::EmptyTag => write!(f, "tags must not be empty"),
+            BuildError::Crypto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<CryptoError> for BuildError {
+    fn from(e: CryptoError) -> Self {
+        BuildError::Crypto(e)
+    }
+}
+
+// a new secret with its optional fields, encrypted by build; keys are checked against the default
+// key length limit, since the builder doesn't know which store the secret goes to
+pub struct SecretBuilder {
+    key: String,
+    value: Option<Zeroizing<Vec<u8>>>,
+    ttl: Option<Duration>,
+    tags: Vec<String>,
+    description: Option<String>,
+}
+
+impl SecretBuilder {
+    pub fn new(key: String) -> Self {
+        SecretBuilder { key, value: None, ttl: None, tags: Vec::new(), description: None }
+    }
+
+    pub fn value(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.value = Some(Zeroizing::new(value.into()));
+        self
+    }
+
+    // counted from when build is called
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn build(self, master_key: &[u8]) -> Result<(String, Secret), BuildError> {
+        if self.key.is_empty() {
+            return Err(BuildError::EmptyKey);
+        }
+        if self.key.len() > DEFAULT_MAX_KEY_LENGTH {
+            return Err(BuildError::KeyTooLong { len: self.key.len(), max: DEFAULT_MAX_KEY_LENGTH });
+        }
+        if self.ttl.is_some_and(|ttl| ttl.as_secs() == 0) {
+            return Err(BuildError::ZeroTtl);
+        }
+        if self.tags.iter().any(String::is_empty) {
+            return Err(BuildError::EmptyTag);
+        }
+        let value = self.value.ok_or(BuildError::MissingValue)?;
+
+        let (iv, encrypted_value) = encrypt_data(master_key, &value)?;
+        let now = unix_now();
+        // duplicate tags are dropped, as add_tag would
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.tags {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        let secret = Secret {
+            iv,
+            encrypted_value,
+            disabled: false,
+            expires_at: self.ttl.map(|ttl| now + ttl.as_secs()),
+            metadata: SecretMetadata { created_at: now, updated_at: now, description: self.description, tags },
+        };
+        Ok((self.key, secret))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct PersistedSecrets {
     pub secrets: HashMap<String, Secret>,
+    // earlier versions of each key, oldest first; absent in files written before versioning
+    #[serde(default)]
+    pub history: HashMap<String, Vec<Secret>>,
 }
 
 pub struct KVStore {
     secrets: RwLock<HashMap<String, Secret>>,
+    // previous versions behind each entry in secrets, oldest first; always locked after secrets
+    history: RwLock<HashMap<String, Vec<Secret>>>,
+    data_dir: PathBuf,
+    last_save: AtomicU64,
+    save_errors: AtomicUsize,
+    invalidated_at: AtomicU64,
+    max_key_length: usize,
+    max_versions: usize,
+    // an Algorithm tag; restore adopts the cipher of the file it reads unless with_algorithm pinned one
+    algorithm: AtomicU8,
+    algorithm_pinned: bool,
+    // held across snapshot and write so concurrent persists land in order, newest last
+    persist_lock: Mutex<()>,
+}
+
+#[derive(Serialize)]
+pub struct StoreInfo {
+    pub total_secrets: usize,
+    pub disabled_secrets: usize,
+    pub expired_secrets: usize,
+    pub total_bytes: usize,
+    // created_at of the oldest and newest secret, leaving out ones stored before metadata was kept
+    pub oldest_secret: Option<u64>,
+    pub newest_secret: Option<u64>,
+    pub last_save: Option<u64>,
+    pub save_errors_since_start: usize,
+}
+
+#[derive(Debug)]
+pub enum TypedGetError {
+    NotFound,
+    DecryptionFailed(CryptoError),
+    DeserializationFailed(String),
+}
+
+impl fmt::Display for TypedGetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypedGetError::NotFound => write!(f, "secret not found"),
+            TypedGetError::DecryptionFailed(e) => write!(f, "failed to decrypt secret: {}", e),
+            TypedGetError::DeserializationFailed(msg) => write!(f, "failed to deserialize secret: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TypedGetError {}
+
+pub const DEFAULT_MAX_KEY_LENGTH: usize = 256;
+pub const DEFAULT_MAX_VERSIONS: usize = 5;
+
+#[derive(Debug)]
+pub enum KeyNameError {
+    TooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for KeyNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyNameError::TooLong { len, max } => write!(f, "key is {} bytes long, the limit is {}", len, max),
+        }
+    }
+}
+
+impl std::error::Error for KeyNameError {}
+
+impl From<KeyNameError> for io::Error {
+    fn from(e: KeyNameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+#[derive(Debug)]
+pub enum CryptoError {
+    InvalidKeyLength(usize),
+    InvalidNonceLength { expected: usize, got: usize },
+    EncryptionFailed,
+    // the tag did not verify: the key is wrong or the ciphertext was changed
+    AuthenticationFailed,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::InvalidKeyLength(len) => write!(f, "key must be 32 bytes, got {}", len),
+            CryptoError::InvalidNonceLength { expected, got } => write!(f, "nonce must be {} bytes, got {}", expected, got),
+            CryptoError::EncryptionFailed => write!(f, "encryption failed"),
+            CryptoError::AuthenticationFailed => write!(f, "authentication failed: wrong key or corrupted data"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+// surfaces as InvalidData so a bad key or tampered file is not mistaken for a missing one (NotFound)
+impl From<CryptoError> for io::Error {
+    fn from(e: CryptoError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+#[derive(Debug)]
+pub enum CounterError {
+    // the value is not a UTF-8 decimal integer
+    NotNumeric,
+    Overflow,
+    Disabled,
+    InvalidKey(KeyNameError),
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for CounterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CounterError::NotNumeric => write!(f, "value is not a decimal integer"),
+            CounterError::Overflow => write!(f, "counter would overflow"),
+            CounterError::Disabled => write!(f, "secret is disabled"),
+            CounterError::InvalidKey(e) => write!(f, "{}", e),
+            CounterError::Crypto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CounterError {}
+
+impl From<CryptoError> for CounterError {
+    fn from(e: CryptoError) -> Self {
+        CounterError::Crypto(e)
+    }
+}
+
+impl Default for KVStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl KVStore {
     pub fn new() -> Self {
+        Self::with_data_dir("data")
+    }
+
+    pub fn with_data_dir(data_dir: impl Into<PathBuf>) -> Self {
         KVStore {
             secrets: RwLock::new(HashMap::new()),
+            history: RwLock::new(HashMap::new()),
+            data_dir: data_dir.into(),
+            last_save: AtomicU64::new(0),
+            save_errors: AtomicUsize::new(0),
+            invalidated_at: AtomicU64::new(0),
+            max_key_length: DEFAULT_MAX_KEY_LENGTH,
+            max_versions: DEFAULT_MAX_VERSIONS,
+            algorithm: AtomicU8::new(Algorithm::default().tag()),
+            algorithm_pinned: false,
+            persist_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn with_max_key_length(mut self, max_key_length: usize) -> Self {
+        self.max_key_length = max_key_length;
+        self
+    }
+
+    // versions kept per key, counting the current one; 1 keeps no history
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        self.max_versions = max_versions.max(1);
+        self
+    }
+
+    // the cipher persist writes the file with; restore reads whichever cipher the file was written
+    // with, and without this a store keeps writing the cipher it last restored
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = AtomicU8::new(algorithm.tag());
+        self.algorithm_pinned = true;
+        self
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        Algorithm::from_tag(self.algorithm.load(Ordering::Relaxed)).unwrap_or_default()
+    }
+
+    pub fn check_key(&self, key: &str) -> Result<(), KeyNameError> {
+        if key.len() > self.max_key_length {
+            return Err(KeyNameError::TooLong { len: key.len(), max: self.max_key_length });
         }
+        Ok(())
     }
 
-    pub async fn set_secret(&self, key: String, iv: Vec<u8>, encrypted_value: Vec<u8>) -> std::io::Result<()> {
+    pub async fn set_secret(&self, key: String, iv: Vec<u8>, encrypted_value: Vec<u8>) -> std::io::Result<SecretWrite> {
+        self.set_secret_expiring(key, iv, encrypted_value, None).await
+    }
+
+    // like set_secret, but the secret reads as absent from expires_at (a unix timestamp) on
+    pub async fn set_secret_expiring(&self, key: String, iv: Vec<u8>, encrypted_value: Vec<u8>, expires_at: Option<u64>) -> std::io::Result<SecretWrite> {
+        self.check_key(&key)?;
+        let now = unix_now();
         let mut secrets = self.secrets.write().await;
-        secrets.insert(key, Secret { iv, encrypted_value });
+        // an expired secret that hasn't been swept yet is replaced as if it were already gone
+        if secrets.get(&key).is_some_and(|secret| secret.is_expired(now)) {
+            self.history.write().await.remove(&key);
+            secrets.remove(&key);
+        }
+        // a disabled secret stays disabled, and keeps its description and tags, when its value is replaced
+        let (disabled, metadata) = match secrets.get(&key) {
+            Some(secret) => (secret.disabled, SecretMetadata { updated_at: now, ..secret.metadata.clone() }),
+            None => (false, SecretMetadata { created_at: now, updated_at: now, ..SecretMetadata::default() }),
+        };
+        let previous = secrets.insert(key.clone(), Secret { iv, encrypted_value, disabled, expires_at, metadata });
+
+        match previous {
+            Some(previous) => {
+                self.push_version(&mut *self.history.write().await, key, previous);
+                Ok(SecretWrite::Updated)
+            }
+            None => Ok(SecretWrite::Created),
+        }
+    }
+
+    // adds delta to a secret holding a decimal integer and returns the result; the read, the
+    // re-encryption and the write all happen under one write lock, so concurrent increments
+    // never lose an update. A missing or expired key counts from 0
+    pub async fn atomic_increment_counter(&self, key: &str, master_key: &[u8], delta: i64) -> Result<i64, CounterError> {
+        self.check_key(key).map_err(CounterError::InvalidKey)?;
+        let now = unix_now();
+        let mut secrets = self.secrets.write().await;
+
+        let current = match secrets.get(key) {
+            Some(secret) if secret.is_expired(now) => None,
+            Some(secret) if secret.disabled => return Err(CounterError::Disabled),
+            current => current,
+        };
+        let value = match current {
+            Some(secret) => {
+                let plaintext = decrypt_data(master_key, &secret.iv, &secret.encrypted_value)?;
+                std::str::from_utf8(&plaintext)
+                    .ok()
+                    .and_then(|text| text.trim().parse::<i64>().ok())
+                    .ok_or(CounterError::NotNumeric)?
+            }
+            None => 0,
+        };
+        let value = value.checked_add(delta).ok_or(CounterError::Overflow)?;
+
+        let (iv, encrypted_value) = encrypt_data(master_key, value.to_string().as_bytes())?;
+        let previous = secrets.remove(key).filter(|secret| !secret.is_expired(now));
+        if previous.is_none() {
+            self.history.write().await.remove(key);
+        }
+        let secret = match &previous {
+            Some(previous) => Secret {
+                iv,
+                encrypted_value,
+                metadata: SecretMetadata { updated_at: now, ..previous.metadata.clone() },
+                ..previous.clone()
+            },
+            None => Secret {
+                iv,
+                encrypted_value,
+                disabled: false,
+                expires_at: None,
+                metadata: SecretMetadata { created_at: now, updated_at: now, ..SecretMetadata::default() },
+            },
+        };
+        secrets.insert(key.to_string(), secret);
+        if let Some(previous) = previous {
+            self.push_version(&mut *self.history.write().await, key.to_string(), previous);
+        }
+        Ok(value)
+    }
+
+    // replaces the description and tags of the current version; false when the key doesn't exist
+    pub async fn set_metadata(&self, key: &str, description: Option<String>, tags: Vec<String>) -> bool {
+        let mut secrets = self.secrets.write().await;
+        match secrets.get_mut(key) {
+            Some(secret) => {
+                secret.metadata.description = description;
+                secret.metadata.tags = tags;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // tags are plain metadata, so these leave the encrypted value alone; both return the tags
+    // afterwards, or None when the key doesn't exist
+    pub async fn add_tag(&self, key: &str, tag: &str) -> Option<Vec<String>> {
+        let mut secrets = self.secrets.write().await;
+        let tags = &mut secrets.get_mut(key)?.metadata.tags;
+        if !tags.iter().any(|existing| existing == tag) {
+            tags.push(tag.to_string());
+        }
+        Some(tags.clone())
+    }
+
+    pub async fn remove_tag(&self, key: &str, tag: &str) -> Option<Vec<String>> {
+        let mut secrets = self.secrets.write().await;
+        let tags = &mut secrets.get_mut(key)?.metadata.tags;
+        tags.retain(|existing| existing != tag);
+        Some(tags.clone())
+    }
+
+    pub async fn get_metadata(&self, key: &str) -> Option<SecretMetadata> {
+        self.get_secret(key).await.map(|secret| secret.metadata)
+    }
+
+    // keys whose current version carries tag, sorted; disabled and expired secrets are left out
+    pub async fn find_by_tag(&self, tag: &str) -> Vec<String> {
+        let now = unix_now();
+        let secrets = self.secrets.read().await;
+        let mut keys: Vec<String> = secrets
+            .iter()
+            .filter(|(_, secret)| !secret.disabled && !secret.is_expired(now))
+            .filter(|(_, secret)| secret.metadata.tags.iter().any(|t| t == tag))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // how many readable secrets carry each tag, most used first and ties by name; tags are plaintext
+    // metadata, so nothing is decrypted
+    pub async fn count_by_tag(&self) -> Vec<(String, usize)> {
+        let now = unix_now();
+        let secrets = self.secrets.read().await;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for secret in secrets.values().filter(|secret| !secret.disabled && !secret.is_expired(now)) {
+            for tag in &secret.metadata.tags {
+                *counts.entry(tag.clone()).or_default() += 1;
+            }
+        }
+        let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+        counts.sort_by(|(a_tag, a_count), (b_tag, b_count)| b_count.cmp(a_count).then_with(|| a_tag.cmp(b_tag)));
+        counts
+    }
+
+    // keeps a replaced secret as the newest earlier version, dropping the oldest past max_versions
+    fn push_version(&self, history: &mut HashMap<String, Vec<Secret>>, key: String, previous: Secret) {
+        let versions = history.entry(key).or_default();
+        versions.push(previous);
+        let excess = versions.len().saturating_sub(self.max_versions - 1);
+        versions.drain(..excess);
+    }
+
+    // folds the secrets of another store file into this one: keys only in other_path are added, and
+    // for keys in both resolver(key, local, remote) returns the secret to keep. Both stay encrypted,
+    // and a local secret that loses is kept in the key's history like any overwritten value
+    pub async fn merge_with_conflict_resolver<F>(&self, other_path: &str, master_key: &[u8], resolver: F) -> io::Result<()>
+    where
+        F: Fn(&str, &Secret, &Secret) -> Secret + Send + Sync,
+    {
+        let remote: PersistedSecrets = serde_json::from_slice(&self.decrypt_file(other_path, master_key).await?)?;
+
+        let mut secrets = self.secrets.write().await;
+        let mut history = self.history.write().await;
+        for (key, remote_secret) in remote.secrets {
+            let winner = match secrets.get(&key) {
+                Some(local) => resolver(&key, local, &remote_secret),
+                None => remote_secret,
+            };
+            // ivs are random per encryption, so an unchanged iv means the local secret won
+            if let Some(previous) = secrets.insert(key.clone(), winner) {
+                if previous.iv != secrets[&key].iv {
+                    self.push_version(&mut history, key, previous);
+                }
+            }
+        }
         Ok(())
     }
 
+    // every secret with its history, decrypted with master_key and sealed under a key derived
+    // from passphrase, so it can be imported on a host with a different master key
+    pub async fn export_bundle(&self, master_key: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+        let open = |secret: &Secret| -> io::Result<BundleSecret> {
+            Ok(BundleSecret {
+                value: decrypt_data(master_key, &secret.iv, &secret.encrypted_value)?.to_vec(),
+                disabled: secret.disabled,
+                expires_at: secret.expires_at,
+                metadata: secret.metadata.clone(),
+            })
+        };
+
+        let secrets = self.secrets.read().await;
+        let history = self.history.read().await;
+        let mut entries = HashMap::new();
+        for (key, secret) in secrets.iter() {
+            let versions = history.get(key).into_iter().flatten().map(open).collect::<io::Result<_>>()?;
+            entries.insert(key.clone(), BundleEntry { current: open(secret)?, history: versions });
+        }
+        let payload = Zeroizing::new(serde_json::to_vec(&entries)?);
+        drop(entries);
+
+        let mut salt = [0u8; BUNDLE_SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let params = argon2::Params::default();
+        let bundle_key = derive_bundle_key(passphrase, &salt, &params)?;
+        let (nonce, ciphertext) = encrypt_data(&bundle_key[..], &payload)?;
+
+        let mut bundle = BUNDLE_MAGIC.to_vec();
+        bundle.push(BUNDLE_VERSION);
+        for cost in [params.m_cost(), params.t_cost(), params.p_cost()] {
+            bundle.extend_from_slice(&cost.to_be_bytes());
+        }
+        for part in [&salt[..], &nonce, &ciphertext] {
+            bundle.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            bundle.extend_from_slice(part);
+        }
+        Ok(bundle)
+    }
+
+    // the inverse of export_bundle, re-encrypting every value under master_key;
+    // returns how many keys were imported
+    pub async fn import_bundle(&self, bundle: &[u8], passphrase: &str, master_key: &[u8], policy: MergePolicy) -> io::Result<usize> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("not a valid bundle: {}", msg));
+        let body = match bundle.strip_prefix(BUNDLE_MAGIC) {
+            Some([BUNDLE_VERSION, body @ ..]) => body,
+            Some(_) => return Err(invalid("unsupported version")),
+            None => return Err(invalid("missing header")),
+        };
+
+        let (costs, body) = body.split_first_chunk::<12>().ok_or_else(|| invalid("truncated header"))?;
+        let cost = |i: usize| u32::from_be_bytes(costs[i * 4..i * 4 + 4].try_into().expect("4-byte slice"));
+        // the costs come from the file, so anything dearer than export_bundle writes is refused
+        // before a forged header can make the key derivation take gigabytes
+        let limits = [argon2::Params::DEFAULT_M_COST, argon2::Params::DEFAULT_T_COST, argon2::Params::DEFAULT_P_COST];
+        if (0..3).any(|i| cost(i) > limits[i]) {
+            return Err(invalid("key derivation costs exceed what barn export writes"));
+        }
+        let params = argon2::Params::new(cost(0), cost(1), cost(2), Some(32)).map_err(|e| invalid(&e.to_string()))?;
+        let (salt, body) = read_length_prefixed(body).ok_or_else(|| invalid("truncated salt"))?;
+        let (nonce, body) = read_length_prefixed(body).ok_or_else(|| invalid("truncated nonce"))?;
+        let (ciphertext, _) = read_length_prefixed(body).ok_or_else(|| invalid("truncated ciphertext"))?;
+
+        let bundle_key = derive_bundle_key(passphrase, salt, &params)?;
+        let payload = decrypt_data(&bundle_key[..], nonce, ciphertext)?;
+        let entries: HashMap<String, BundleEntry> = serde_json::from_slice(&payload)?;
+
+        let seal = |version: BundleSecret| -> io::Result<Secret> {
+            let value = Zeroizing::new(version.value);
+            let (iv, encrypted_value) = encrypt_data(master_key, &value)?;
+            Ok(Secret { iv, encrypted_value, disabled: version.disabled, expires_at: version.expires_at, metadata: version.metadata })
+        };
+
+        let mut secrets = self.secrets.write().await;
+        let mut history = self.history.write().await;
+        // every entry is checked and sealed before the store changes, so a bad one imports nothing
+        let mut staged = Vec::new();
+        for (key, entry) in entries {
+            self.check_key(&key)?;
+            if policy == MergePolicy::SkipExisting && secrets.contains_key(&key) {
+                continue;
+            }
+            // the oldest versions past max_versions are dropped, as push_version does
+            let excess = entry.history.len().saturating_sub(self.max_versions - 1);
+            let versions = entry.history.into_iter().skip(excess).map(seal).collect::<io::Result<Vec<_>>>()?;
+            staged.push((key, seal(entry.current)?, versions));
+        }
+
+        let imported = staged.len();
+        for (key, current, versions) in staged {
+            secrets.insert(key.clone(), current);
+            history.insert(key, versions);
+        }
+        history.retain(|_, versions| !versions.is_empty());
+        Ok(imported)
+    }
+
+    // n counts back from the current version, so 0 is what get_secret returns (disabled or not)
+    pub async fn get_secret_version(&self, key: &str, n: usize) -> Option<Secret> {
+        let secrets = self.secrets.read().await;
+        let current = secrets.get(key).filter(|secret| !secret.is_expired(unix_now()))?;
+        if n == 0 {
+            return Some(current.clone());
+        }
+
+        let history = self.history.read().await;
+        let versions = history.get(key)?;
+        versions.len().checked_sub(n).map(|i| versions[i].clone())
+    }
+
+    // every kept version of key, newest first, so the index matches get_secret_version
+    pub async fn list_versions(&self, key: &str) -> Vec<Secret> {
+        let secrets = self.secrets.read().await;
+        let current = match secrets.get(key) {
+            Some(current) if !current.is_expired(unix_now()) => current.clone(),
+            _ => return Vec::new(),
+        };
+
+        let history = self.history.read().await;
+        let older = history.get(key).into_iter().flatten().rev().cloned();
+        std::iter::once(current).chain(older).collect()
+    }
+
+    // drops the current version and makes the one before it current;
+    // returns false, changing nothing, when there is no earlier version
+    pub async fn rollback(&self, key: &str) -> bool {
+        let mut secrets = self.secrets.write().await;
+        let mut history = self.history.write().await;
+        let previous = match history.get_mut(key).and_then(Vec::pop) {
+            Some(previous) => previous,
+            None => return false,
+        };
+
+        if history.get(key).is_some_and(Vec::is_empty) {
+            history.remove(key);
+        }
+        secrets.insert(key.to_string(), previous);
+        true
+    }
+
+    // disabled secrets read as absent until they are enabled again;
+    // expired ones read as absent and are removed on the way out
     pub async fn get_secret(&self, key: &str) -> Option<Secret> {
+        // a key that could never have been stored is not worth hashing
+        self.check_key(key).ok()?;
+        let now = unix_now();
+        {
+            let secrets = self.secrets.read().await;
+            match secrets.get(key) {
+                Some(secret) if secret.is_expired(now) => {}
+                Some(secret) => return (!secret.disabled).then(|| secret.clone()),
+                None => return None,
+            }
+        }
+
+        let mut secrets = self.secrets.write().await;
+        if secrets.get(key).is_some_and(|secret| secret.is_expired(now)) {
+            self.history.write().await.remove(key);
+            secrets.remove(key);
+        }
+        None
+    }
+
+    // get_secret on self and then on each store in chain, stopping at the first hit that opens
+    // under master_key; a store written under another key is passed over rather than shadowing
+    // the rest of the chain
+    pub async fn get_secret_with_fallback_chain(&self, key: &str, chain: &[&KVStore], master_key: &[u8]) -> Option<Secret> {
+        self.get_secret_with_fallback_index(key, chain, master_key).await.map(|(_, secret)| secret)
+    }
+
+    // get_secret_with_fallback_chain, also saying which store served the secret: 0 is self and
+    // i is chain[i - 1]
+    pub async fn get_secret_with_fallback_index(&self, key: &str, chain: &[&KVStore], master_key: &[u8]) -> Option<(usize, Secret)> {
+        for (i, store) in std::iter::once(self).chain(chain.iter().copied()).enumerate() {
+            if let Some(secret) = store.get_secret(key).await {
+                if decrypt_data(master_key, &secret.iv, &secret.encrypted_value).is_ok() {
+                    return Some((i, secret));
+                }
+            }
+        }
+        None
+    }
+
+    // get_secret(key).is_some() without cloning the secret or removing it when expired
+    pub async fn contains_key(&self, key: &str) -> bool {
+        let now = unix_now();
+        let secrets = self.secrets.read().await;
+        secrets.get(key).is_some_and(|secret| !secret.disabled && !secret.is_expired(now))
+    }
+
+    // expired secrets that have not been swept yet, sorted by key
+    pub async fn expired_secrets(&self) -> Vec<(String, Secret)> {
+        let now = unix_now();
+        let secrets = self.secrets.read().await;
+        let mut expired: Vec<(String, Secret)> = secrets
+            .iter()
+            .filter(|(_, secret)| secret.is_expired(now))
+            .map(|(key, secret)| (key.clone(), secret.clone()))
+            .collect();
+        expired.sort_by(|a, b| a.0.cmp(&b.0));
+        expired
+    }
+
+    // removes every expired secret and its history, returning how many there were
+    pub async fn sweep_expired(&self) -> usize {
+        let now = unix_now();
+        let mut secrets = self.secrets.write().await;
+        let mut history = self.history.write().await;
+        let before = secrets.len();
+        secrets.retain(|key, secret| {
+            let keep = !secret.is_expired(now);
+            if !keep {
+                history.remove(key);
+            }
+            keep
+        });
+        before - secrets.len()
+    }
+
+    // every key that has not expired, sorted so output is stable
+    pub async fn list_keys(&self) -> Vec<String> {
+        let now = unix_now();
+        let secrets = self.secrets.read().await;
+        let mut keys: Vec<String> = secrets
+            .iter()
+            .filter(|(_, secret)| !secret.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // returns whether the key was there to remove; its earlier versions go with it
+    pub async fn delete_secret(&self, key: &str) -> bool {
+        let mut secrets = self.secrets.write().await;
+        self.history.write().await.remove(key);
+        secrets.remove(key).is_some()
+    }
+
+    // removes every key under a single write lock, reporting which ones existed
+    pub async fn batch_delete(&self, keys: &[&str]) -> HashMap<String, bool> {
+        let mut secrets = self.secrets.write().await;
+        let mut history = self.history.write().await;
+        keys.iter()
+            .map(|key| {
+                history.remove(*key);
+                (key.to_string(), secrets.remove(*key).is_some())
+            })
+            .collect()
+    }
+
+    // empties the store so a shutdown flush can serialize without holding the lock; history is
+    // dropped too, so a key set again later doesn't pick up versions of the drained value
+    pub async fn drain_to_vec(&self) -> Vec<(String, Secret)> {
+        let mut secrets = self.secrets.write().await;
+        self.history.write().await.clear();
+        secrets.drain().collect()
+    }
+
+    pub async fn is_disabled(&self, key: &str) -> bool {
         let secrets = self.secrets.read().await;
-        secrets.get(key).cloned()
+        secrets.get(key).is_some_and(|secret| secret.disabled)
     }
 
+    // returns false when the key doesn't exist
+    pub async fn disable_secret(&self, key: &str) -> bool {
+        self.set_disabled(key, true).await
+    }
+
+    pub async fn enable_secret(&self, key: &str) -> bool {
+        self.set_disabled(key, false).await
+    }
+
+    async fn set_disabled(&self, key: &str, disabled: bool) -> bool {
+        let mut secrets = self.secrets.write().await;
+        match secrets.get_mut(key) {
+            Some(secret) => {
+                secret.disabled = disabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // decrypts the secret and parses it as JSON into T
+    pub async fn get_secret_typed<T: DeserializeOwned>(&self, key: &str, master_key: &[u8]) -> Result<Option<T>, TypedGetError> {
+        let secret = match self.get_secret(key).await {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+
+        let plaintext = decrypt_data(master_key, &secret.iv, &secret.encrypted_value).map_err(TypedGetError::DecryptionFailed)?;
+        serde_json::from_slice(&plaintext)
+            .map(Some)
+            .map_err(|e| TypedGetError::DeserializationFailed(e.to_string()))
+    }
+
+    // one read lock for the whole batch; a key that is missing, disabled or expired gets NotFound
+    pub async fn get_many_typed<T: DeserializeOwned>(&self, keys: &[&str], master_key: &[u8]) -> HashMap<String, Result<T, TypedGetError>> {
+        let now = unix_now();
+        let secrets = self.secrets.read().await;
+        keys.iter()
+            .map(|key| {
+                let value = match secrets.get(*key).filter(|secret| !secret.disabled && !secret.is_expired(now)) {
+                    Some(secret) => decrypt_data(master_key, &secret.iv, &secret.encrypted_value)
+                        .map_err(TypedGetError::DecryptionFailed)
+                        .and_then(|plaintext| {
+                            serde_json::from_slice(&plaintext).map_err(|e| TypedGetError::DeserializationFailed(e.to_string()))
+                        }),
+                    None => Err(TypedGetError::NotFound),
+                };
+                (key.to_string(), value)
+            })
+            .collect()
+    }
+
+    pub async fn set_secret_typed<T: Serialize>(&self, key: String, value: &T, master_key: &[u8]) -> std::io::Result<SecretWrite> {
+        let plaintext = Zeroizing::new(serde_json::to_vec(value)?);
+        let (iv, encrypted_value) = encrypt_data(master_key, &plaintext)?;
+        self.set_secret(key, iv, encrypted_value).await
+    }
+
+    // encrypted_data must come from this store's cipher, the header is tagged with it;
+    // a crash mid-write leaves the previous file in place, see write_atomically
     pub async fn save_to_file_encrypted(&self, filename: &str, encrypted_data: &[u8], nonce: &[u8]) -> std::io::Result<()> {
-        let mut file = File::create(filename)?;
-        file.write_all(nonce)?;
-        file.write_all(encrypted_data)?;
+        write_atomically(filename, &encode_encrypted_file(self.algorithm(), nonce, encrypted_data))
+    }
+
+    // writes the whole store, encrypted under master_key, to {data_dir}/checkpoints/{label}_{timestamp}.bin
+    pub async fn checkpoint(&self, label: &str, master_key: &[u8]) -> io::Result<()> {
+        if label.is_empty() || label.contains(['/', '\\']) || label.contains("..") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid checkpoint label"));
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?
+            .as_secs();
+
+        let dir = self.data_dir.join("checkpoints");
+        fs::create_dir_all(&dir)?;
+
+        let (iv, encrypted_data) = self.encrypt_snapshot(master_key).await?;
+        write_atomically(dir.join(format!("{}_{}.bin", label, timestamp)), &encode_encrypted_file(self.algorithm(), &iv, &encrypted_data))
+    }
+
+    // replaces the in-memory secrets with the contents of a checkpoint file
+    pub async fn restore_checkpoint(&self, path: &str, master_key: &[u8]) -> io::Result<()> {
+        self.restore(path, master_key).await
+    }
+
+    // encrypts the whole store under master_key and writes it to filename
+    pub async fn persist(&self, filename: &str, master_key: &[u8]) -> io::Result<()> {
+        let _guard = self.persist_lock.lock().await;
+        let (iv, encrypted_data) = self.encrypt_snapshot(master_key).await?;
+        match self.save_to_file_encrypted(filename, &encrypted_data, &iv).await {
+            Ok(()) => {
+                self.last_save.store(unix_now(), Ordering::Relaxed);
+                Ok(())
+            }
+            Err(e) => {
+                self.save_errors.fetch_add(1, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    // diagnostics that need no decryption
+    pub async fn info(&self) -> StoreInfo {
+        let secrets = self.secrets.read().await;
+        let last_save = self.last_save.load(Ordering::Relaxed);
+        let now = unix_now();
+        let created = secrets.values().map(|secret| secret.metadata.created_at).filter(|&created_at| created_at > 0);
+
+        StoreInfo {
+            total_secrets: secrets.len(),
+            disabled_secrets: secrets.values().filter(|secret| secret.disabled).count(),
+            expired_secrets: secrets.values().filter(|secret| secret.is_expired(now)).count(),
+            total_bytes: secrets.values().map(|secret| secret.iv.len() + secret.encrypted_value.len()).sum(),
+            oldest_secret: created.clone().min(),
+            newest_secret: created.max(),
+            last_save: (last_save > 0).then_some(last_save),
+            save_errors_since_start: self.save_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    // drops everything held in memory and reloads filename, leaving the store empty when it does not exist
+    pub async fn invalidate_cache(&self, filename: &str, master_key: &[u8]) -> io::Result<()> {
+        if std::path::Path::new(filename).exists() {
+            self.restore(filename, master_key).await?;
+        } else {
+            let mut secrets = self.secrets.write().await;
+            secrets.clear();
+            self.history.write().await.clear();
+        }
+
+        self.invalidated_at.store(unix_now(), Ordering::Relaxed);
         Ok(())
-    }    
+    }
+
+    // seconds since the epoch of the last invalidate_cache, if there was one
+    pub fn invalidated_at(&self) -> Option<u64> {
+        let invalidated_at = self.invalidated_at.load(Ordering::Relaxed);
+        (invalidated_at > 0).then_some(invalidated_at)
+    }
+
+    // re-encrypts every secret and the file around them under new_key, leaving the store holding
+    // the re-encrypted secrets; the file is replaced in one rename so a crash leaves the old one intact
+    pub async fn rotate_master_key(&self, old_key: &[u8], new_key: &[u8], filename: &str) -> io::Result<()> {
+        self.restore(filename, old_key).await?;
+
+        let mut secrets = self.secrets.write().await;
+        let mut history = self.history.write().await;
+        for secret in secrets.values_mut().chain(history.values_mut().flatten()) {
+            let plaintext = decrypt_data(old_key, &secret.iv, &secret.encrypted_value)?;
+            (secret.iv, secret.encrypted_value) = encrypt_data(new_key, &plaintext)?;
+        }
+
+        let plaintext = Zeroizing::new(serde_json::to_vec(&PersistedSecrets { secrets: secrets.clone(), history: history.clone() })?);
+        let (iv, encrypted_data) = self.algorithm().cipher().encrypt(new_key, &plaintext)?;
+        self.save_to_file_encrypted(filename, &encrypted_data, &iv).await
+    }
+
+    // replaces the in-memory secrets with a file written by persist
+    pub async fn restore(&self, filename: &str, master_key: &[u8]) -> io::Result<()> {
+        let (algorithm, iv, encrypted_data) = read_encrypted_file(filename)?;
+        let plaintext = algorithm.cipher().decrypt(master_key, &iv, &encrypted_data)?;
+        let persisted: PersistedSecrets = serde_json::from_slice(&plaintext)?;
+        if !self.algorithm_pinned {
+            self.algorithm.store(algorithm.tag(), Ordering::Relaxed);
+        }
+
+        let mut secrets = self.secrets.write().await;
+        *secrets = persisted.secrets;
+        *self.history.write().await = persisted.history;
+        Ok(())
+    }
+
+    // the encrypted store exactly as it would be written to disk
+    pub async fn snapshot_to_reader(&self, master_key: &[u8]) -> io::Result<impl tokio::io::AsyncRead> {
+        // built in memory for now; the AsyncRead return type leaves room for real streaming later
+        let (iv, encrypted_data) = self.encrypt_snapshot(master_key).await?;
+        Ok(io::Cursor::new(encode_encrypted_file(self.algorithm(), &iv, &encrypted_data)))
+    }
+
+    async fn encrypt_snapshot(&self, master_key: &[u8]) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let secrets = self.secrets.read().await;
+        let history = self.history.read().await;
+        let plaintext = Zeroizing::new(serde_json::to_vec(&PersistedSecrets { secrets: secrets.clone(), history: history.clone() })?);
+        Ok(self.algorithm().cipher().encrypt(master_key, &plaintext)?)
+    }
 
+    // decrypts filename with whichever cipher its header names
+    pub async fn decrypt_file(&self, filename: &str, master_key: &[u8]) -> io::Result<Zeroizing<Vec<u8>>> {
+        let (algorithm, iv, encrypted_data) = read_encrypted_file(filename)?;
+        Ok(algorithm.cipher().decrypt(master_key, &iv, &encrypted_data)?)
+    }
+
+    // the raw iv and ciphertext; decrypt_file also picks the cipher the file was written with
     pub async fn load_from_file_encrypted(&self, filename: &str) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
-        let mut file = File::open(filename)?;
-        let mut nonce = vec![0u8; 24];
-        file.read_exact(&mut nonce)?;
-        let mut encrypted_data = Vec::new();
-        file.read_to_end(&mut encrypted_data)?;
-        Ok((nonce, encrypted_data))
+        let (_, iv, encrypted_data) = read_encrypted_file(filename)?;
+        Ok((iv, encrypted_data))
+    }
+}
+
+// files written before the header existed are a bare 24-byte iv followed by the ciphertext,
+// and version 1 headers predate the algorithm tag; both are XChaCha20-Poly1305
+fn read_encrypted_file(filename: &str) -> io::Result<(Algorithm, Vec<u8>, Vec<u8>)> {
+    let mut contents = Vec::new();
+    File::open(filename)?.read_to_end(&mut contents)?;
+
+    let (algorithm, body) = match contents.strip_prefix(FILE_MAGIC) {
+        Some([FILE_VERSION_UNTAGGED, body @ ..]) => (Algorithm::XChaCha20Poly1305, body),
+        Some([FILE_VERSION, tag, body @ ..]) => {
+            let algorithm = Algorithm::from_tag(*tag).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{} is encrypted with unknown cipher {}", filename, tag))
+            })?;
+            (algorithm, body)
+        }
+        Some([FILE_VERSION]) => return Err(truncated(filename, "header")),
+        Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has an unsupported format version", filename))),
+        None if contents.len() < 24 => return Err(truncated(filename, "iv")),
+        None => return Ok((Algorithm::XChaCha20Poly1305, contents[..24].to_vec(), contents[24..].to_vec())),
+    };
+
+    let (nonce, rest) = read_length_prefixed(body).ok_or_else(|| truncated(filename, "iv"))?;
+    let (encrypted_data, rest) = read_length_prefixed(rest).ok_or_else(|| truncated(filename, "ciphertext"))?;
+    if !rest.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has trailing data after the ciphertext", filename)));
+    }
+    Ok((algorithm, nonce.to_vec(), encrypted_data.to_vec()))
+}
+
+#[derive(Debug)]
+pub enum SiloNameError {
+    Empty,
+    // anything that could escape silos/ once it is part of a file name
+    PathTraversal,
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for SiloNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiloNameError::Empty => write!(f, "silo name must not be empty"),
+            SiloNameError::PathTraversal => write!(f, "silo name must not contain '..', '/' or '\\'"),
+            SiloNameError::InvalidCharacter(c) => write!(f, "silo name must not contain {:?}", c),
+        }
+    }
+}
+
+impl std::error::Error for SiloNameError {}
+
+impl From<SiloNameError> for io::Error {
+    fn from(e: SiloNameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+// names come from request paths, so only letters, digits, '-', '_' and '.' get near the filesystem
+pub fn validate_silo_name(name: &str) -> Result<(), SiloNameError> {
+    if name.is_empty() {
+        return Err(SiloNameError::Empty);
+    }
+    if name.contains("..") || name.contains(['/', '\\']) {
+        return Err(SiloNameError::PathTraversal);
+    }
+    match name.chars().find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))) {
+        Some(c) => Err(SiloNameError::InvalidCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+// named KVStores, each persisted to {data_dir}/silos/{name}.bin, so tenants never share a keyspace
+pub struct SiloManager {
+    silos_dir: PathBuf,
+    max_key_length: usize,
+    silos: RwLock<HashMap<String, Arc<KVStore>>>,
+}
+
+impl SiloManager {
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        SiloManager {
+            silos_dir: data_dir.into().join("silos"),
+            max_key_length: DEFAULT_MAX_KEY_LENGTH,
+            silos: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_max_key_length(mut self, max_key_length: usize) -> Self {
+        self.max_key_length = max_key_length;
+        self
+    }
+
+    pub fn silos_dir(&self) -> &Path {
+        &self.silos_dir
+    }
+
+    pub fn silo_file(&self, name: &str) -> Result<PathBuf, SiloNameError> {
+        validate_silo_name(name)?;
+        Ok(self.silos_dir.join(format!("{}.bin", name)))
+    }
+
+    // the silo if it is loaded or has a file, None when it was never created
+    pub async fn get_silo(&self, name: &str, master_key: &[u8]) -> io::Result<Option<Arc<KVStore>>> {
+        let path = self.silo_file(name)?;
+        if let Some(silo) = self.silos.read().await.get(name) {
+            return Ok(Some(silo.clone()));
+        }
+        if !path.exists() {
+            return Ok(None);
+        }
+        self.load_silo(name, &path, master_key).await.map(Some)
+    }
+
+    pub async fn get_or_create_silo(&self, name: &str, master_key: &[u8]) -> io::Result<Arc<KVStore>> {
+        let path = self.silo_file(name)?;
+        if let Some(silo) = self.silos.read().await.get(name) {
+            return Ok(silo.clone());
+        }
+        self.load_silo(name, &path, master_key).await
+    }
+
+    async fn load_silo(&self, name: &str, path: &Path, master_key: &[u8]) -> io::Result<Arc<KVStore>> {
+        let mut silos = self.silos.write().await;
+        // another request may have loaded it while we waited for the lock
+        if let Some(silo) = silos.get(name) {
+            return Ok(silo.clone());
+        }
+
+        let silo = KVStore::with_data_dir(&self.silos_dir).with_max_key_length(self.max_key_length);
+        if path.exists() {
+            silo.restore(path.to_str().unwrap_or_default(), master_key).await?;
+        }
+        let silo = Arc::new(silo);
+        silos.insert(name.to_string(), silo.clone());
+        Ok(silo)
+    }
+
+    pub async fn persist_silo(&self, name: &str, master_key: &[u8]) -> io::Result<()> {
+        let path = self.silo_file(name)?;
+        let silo = match self.silos.read().await.get(name) {
+            Some(silo) => silo.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("silo {} is not loaded", name))),
+        };
+        fs::create_dir_all(&self.silos_dir)?;
+        silo.persist(path.to_str().unwrap_or_default(), master_key).await
+    }
+
+    // copies every secret and its history into a new silo and saves it, returning the number of keys;
+    // re_nonce decrypts each value and seals it again under a fresh nonce instead of copying the
+    // ciphertext, so nothing in the clone can be matched byte-for-byte against the source; both
+    // stay under master_key, which every silo shares
+    pub async fn clone_silo(&self, source: &str, target: &str, master_key: &[u8], re_nonce: bool) -> io::Result<usize> {
+        let target_path = self.silo_file(target)?;
+        if target_path.exists() || self.silos.read().await.contains_key(target) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("silo {} already exists", target)));
+        }
+        let source = match self.get_silo(source, master_key).await? {
+            Some(silo) => silo,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("silo {} does not exist", source))),
+        };
+
+        let mut secrets = source.secrets.read().await.clone();
+        let mut history = source.history.read().await.clone();
+        if re_nonce {
+            for secret in secrets.values_mut().chain(history.values_mut().flatten()) {
+                let plaintext = decrypt_data(master_key, &secret.iv, &secret.encrypted_value)?;
+                (secret.iv, secret.encrypted_value) = encrypt_data(master_key, &plaintext)?;
+            }
+        }
+        let count = secrets.len();
+
+        let clone = KVStore::with_data_dir(&self.silos_dir)
+            .with_max_key_length(self.max_key_length)
+            .with_algorithm(source.algorithm());
+        *clone.secrets.write().await = secrets;
+        *clone.history.write().await = history;
+        self.silos.write().await.insert(target.to_string(), Arc::new(clone));
+        self.persist_silo(target, master_key).await?;
+        Ok(count)
+    }
+
+    // silos on disk plus any created since the last save, sorted
+    pub async fn list_silos(&self) -> io::Result<Vec<String>> {
+        let mut names: Vec<String> = self.silos.read().await.keys().cloned().collect();
+        match fs::read_dir(&self.silos_dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let file_name = entry?.file_name();
+                    let name = file_name.to_str().and_then(|name| name.strip_suffix(".bin"));
+                    if let Some(name) = name.filter(|name| validate_silo_name(name).is_ok()) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
     }
 }
 
-pub fn encrypt_data(key: &[u8], plaintext: &[u8]) -> (Vec<u8>, Vec<u8>) {
-    let key = Key::from_slice(key);
-    let cipher = XChaCha20Poly1305::new(key);
-    let mut iv = [0u8; 24];
+// what import_bundle does with a key that is already in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    SkipExisting,
+    // the bundle's value and history replace the local ones
+    Overwrite,
+}
+
+// a bundle is magic, version, the argon2id m/t/p costs as u32 big-endian, then length-prefixed salt,
+// nonce and XChaCha20-Poly1305 ciphertext of the JSON entries
+const BUNDLE_MAGIC: &[u8] = b"BARNBNDL";
+const BUNDLE_VERSION: u8 = 1;
+const BUNDLE_SALT_LEN: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+struct BundleSecret {
+    value: Vec<u8>,
+    disabled: bool,
+    expires_at: Option<u64>,
+    metadata: SecretMetadata,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundleEntry {
+    current: BundleSecret,
+    // oldest first, as in KVStore::history
+    history: Vec<BundleSecret>,
+}
+
+fn derive_bundle_key(passphrase: &str, salt: &[u8], params: &argon2::Params) -> io::Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params.clone())
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    Ok(key)
+}
+
+// marks the length-prefixed layout: magic, version, algorithm tag, then u32 big-endian length + iv,
+// then the same for the ciphertext
+const FILE_MAGIC: &[u8] = b"BARN";
+const FILE_VERSION: u8 = 2;
+// the same layout without the algorithm tag
+const FILE_VERSION_UNTAGGED: u8 = 1;
+
+fn encode_encrypted_file(algorithm: Algorithm, iv: &[u8], encrypted_data: &[u8]) -> Vec<u8> {
+    let mut contents = FILE_MAGIC.to_vec();
+    contents.extend_from_slice(&[FILE_VERSION, algorithm.tag()]);
+    for part in [iv, encrypted_data] {
+        contents.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        contents.extend_from_slice(part);
+    }
+    contents
+}
+
+// splits one length-prefixed part off the front, None when the data is shorter than it claims
+fn read_length_prefixed(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = data.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+fn truncated(filename: &str, part: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} is truncated: the {} is incomplete", filename, part))
+}
+
+// writes to a uniquely named temp file beside path, syncs it and renames it over path, which is
+// atomic on one filesystem; concurrent writers never share a temp file
+pub fn write_atomically(path: impl AsRef<Path>, contents: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(contents)?;
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+// a value stored through /store: the 24-byte nonce followed by the ciphertext, replaced
+// atomically so a crash mid-write keeps the previous value readable
+pub fn write_value_file(path: impl AsRef<Path>, master_key: &[u8], value: &[u8]) -> io::Result<()> {
+    let (nonce, ciphertext) = encrypt_data(master_key, value)?;
+    write_atomically(path, &[nonce, ciphertext].concat())
+}
+
+// an AEAD the store can encrypt files with; keys are always 32 bytes
+pub trait Cipher: Sync {
+    fn name(&self) -> &'static str;
+    fn nonce_len(&self) -> usize;
+    fn encrypt(&self, key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptoError>;
+    // the plaintext is wiped from memory when it is dropped
+    fn decrypt(&self, key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError>;
+}
+
+pub struct XChaCha20Poly1305Cipher;
+
+impl Cipher for XChaCha20Poly1305Cipher {
+    fn name(&self) -> &'static str {
+        "XChaCha20Poly1305"
+    }
+
+    fn nonce_len(&self) -> usize {
+        24
+    }
+
+    fn encrypt(&self, key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        aead_encrypt::<XChaCha20Poly1305>(key, self.nonce_len(), plaintext)
+    }
+
+    fn decrypt(&self, key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        aead_decrypt::<XChaCha20Poly1305>(key, self.nonce_len(), nonce, ciphertext)
+    }
+}
+
+// 12-byte random nonces, so keep the number of files written under one key well below 2^32
+pub struct Aes256GcmCipher;
+
+impl Cipher for Aes256GcmCipher {
+    fn name(&self) -> &'static str {
+        "AES-256-GCM"
+    }
+
+    fn nonce_len(&self) -> usize {
+        12
+    }
+
+    fn encrypt(&self, key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        aead_encrypt::<Aes256Gcm>(key, self.nonce_len(), plaintext)
+    }
+
+    fn decrypt(&self, key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        aead_decrypt::<Aes256Gcm>(key, self.nonce_len(), nonce, ciphertext)
+    }
+}
+
+fn aead_encrypt<C: Aead + KeyInit>(key: &[u8], nonce_len: usize, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+    let cipher = C::new_from_slice(key).map_err(|_| CryptoError::InvalidKeyLength(key.len()))?;
+    let mut iv = vec![0u8; nonce_len];
     OsRng.fill_bytes(&mut iv);
-    let nonce = XNonce::from_slice(&iv);
-    let ciphertext = cipher.encrypt(nonce, plaintext).expect("encryption failure!");
-    (iv.to_vec(), ciphertext)
-}
-
-pub fn decrypt_data(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Vec<u8> {
-    let key = Key::from_slice(key);
-    let cipher = XChaCha20Poly1305::new(key);
-    let nonce = XNonce::from_slice(iv);
-    let plaintext = cipher.decrypt(nonce, ciphertext).expect("decryption failure!");
-    plaintext
-}
\ No newline at end of file
+    let ciphertext = cipher.encrypt(aead::Nonce::<C>::from_slice(&iv), plaintext).map_err(|_| CryptoError::EncryptionFailed)?;
+    Ok((iv, ciphertext))
+}
+
+fn aead_decrypt<C: Aead + KeyInit>(key: &[u8], nonce_len: usize, iv: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let cipher = C::new_from_slice(key).map_err(|_| CryptoError::InvalidKeyLength(key.len()))?;
+    if iv.len() != nonce_len {
+        return Err(CryptoError::InvalidNonceLength { expected: nonce_len, got: iv.len() });
+    }
+    cipher
+        .decrypt(aead::Nonce::<C>::from_slice(iv), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| CryptoError::AuthenticationFailed)
+}
+
+// the cipher a file was written with, stored as one byte in its header
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    XChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 2] = [Algorithm::XChaCha20Poly1305, Algorithm::Aes256Gcm];
+
+    pub fn tag(self) -> u8 {
+        match self {
+            Algorithm::XChaCha20Poly1305 => 1,
+            Algorithm::Aes256Gcm => 2,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.tag() == tag)
+    }
+
+    pub fn cipher(self) -> &'static dyn Cipher {
+        match self {
+            Algorithm::XChaCha20Poly1305 => &XChaCha20Poly1305Cipher,
+            Algorithm::Aes256Gcm => &Aes256GcmCipher,
+        }
+    }
+}
+
+// secret values and anything without a header are always XChaCha20-Poly1305
+pub fn encrypt_data(key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+    XChaCha20Poly1305Cipher.encrypt(key, plaintext)
+}
+
+// for commands that only read: a missing key is an error rather than a fresh random key that
+// could never open the store
+pub fn load_key(path: &str) -> io::Result<Zeroizing<Vec<u8>>> {
+    match fs::read(path).map(Zeroizing::new) {
+        Ok(key) if key.len() == 32 => Ok(key),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} does not hold a 32-byte key", path))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no key at {}: none has been created yet, or the store is --sealed and keeps it only as shares", path),
+        )),
+        Err(e) => Err(io::Error::new(e.kind(), format!("Failed to read {}: {}", path, e))),
+    }
+}
+
+pub fn decrypt_data(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    XChaCha20Poly1305Cipher.decrypt(key, iv, ciphertext)
+}
+
+// reads the master key from path, generating and writing a fresh one the first time
+pub fn get_or_create_key(path: &str) -> std::io::Result<Zeroizing<Vec<u8>>> {
+    match fs::read(path).map(Zeroizing::new) {
+        Ok(key) if key.len() == 32 => Ok(key),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} does not hold a 32-byte key", path))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if let Some(parent) = std::path::Path::new(path).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut key = Zeroizing::new(vec![0u8; 32]);
+            OsRng.fill_bytes(&mut key);
+
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            options.open(path)?.write_all(&key)?;
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// threshold-of-total, saved next to the secrets file so split and reconstruct agree
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ShareConfig {
+    pub threshold: u8,
+    pub total: u8,
+}
+
+impl ShareConfig {
+    pub fn new(threshold: u8, total: u8) -> Result<Self, ShareError> {
+        if threshold < 2 {
+            return Err(ShareError::InvalidConfig("threshold must be at least 2"));
+        }
+        if threshold > total {
+            return Err(ShareError::InvalidConfig("threshold cannot exceed the number of shares"));
+        }
+        Ok(ShareConfig { threshold, total })
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let config: ShareConfig = serde_json::from_slice(&fs::read(path)?)?;
+        ShareConfig::new(config.threshold, config.total)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+#[derive(Debug)]
+pub enum ShareError {
+    InvalidConfig(&'static str),
+    InvalidShare(&'static str),
+    RecoveryFailed(String),
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareError::InvalidConfig(msg) => write!(f, "invalid share configuration: {}", msg),
+            ShareError::InvalidShare(msg) => write!(f, "invalid share: {}", msg),
+            ShareError::RecoveryFailed(msg) => write!(f, "failed to recover DEK: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ShareError {}
+
+pub fn split_dek(dek: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>, ShareError> {
+    let config = ShareConfig::new(threshold, shares)?;
+    Ok(Sharks(config.threshold).dealer(dek).take(config.total as usize).collect())
+}
+
+pub fn reconstruct_dek(shares: &[Share], threshold: u8) -> Result<Zeroizing<Vec<u8>>, ShareError> {
+    if threshold < 2 {
+        return Err(ShareError::InvalidConfig("threshold must be at least 2"));
+    }
+    Sharks(threshold)
+        .recover(shares)
+        .map(Zeroizing::new)
+        .map_err(|e| ShareError::RecoveryFailed(e.to_string()))
+}
+
+// a share as bytes is its x coordinate followed by the y values, one byte per byte of the DEK
+pub trait ShareSerialization: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ShareError>;
+}
+
+impl ShareSerialization for Share {
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::from(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ShareError> {
+        match bytes.first() {
+            None => Err(ShareError::InvalidShare("share is empty")),
+            Some(_) if bytes.len() < 2 => Err(ShareError::InvalidShare("share is truncated, it has no y values")),
+            // x = 0 is where the DEK itself sits on the polynomial, the dealer never hands it out
+            Some(0) => Err(ShareError::InvalidShare("share has x coordinate 0")),
+            Some(_) => Share::try_from(bytes).map_err(ShareError::InvalidShare),
+        }
+    }
+}
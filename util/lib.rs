@@ -0,0 +1,8 @@
+pub mod access_control;
+pub mod audit;
+pub mod kv_silo;
+pub mod login_throttle;
+pub mod rate_limit;
+pub mod tokens;
+pub mod users;
+pub mod vault;
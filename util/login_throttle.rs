@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+// the first lockout lasts this long, each one after it twice as long as the last, up to max_backoff
+const BASE_BACKOFF: u64 = 30;
+// a login that would go over max_failures if the ones still running fail is asked to come back after this
+const IN_FLIGHT_RETRY: u64 = 1;
+const USER_PREFIX: &str = "user:";
+
+#[derive(Default)]
+struct Attempts {
+    failures: u32,
+    window_start: u64,
+    lockouts: u32,
+    locked_until: u64,
+    // password checks started but not finished yet
+    in_flight: u32,
+}
+
+// failed logins counted per username and per client address; either one reaching max_failures
+// within window seconds locks that name or address out before bcrypt ever runs
+pub struct LoginThrottle {
+    attempts: RwLock<HashMap<String, Attempts>>,
+    max_failures: u32,
+    window: u64,
+    max_backoff: u64,
+}
+
+impl LoginThrottle {
+    pub fn new(max_failures: u32, window: u64, max_backoff: u64) -> Self {
+        LoginThrottle { attempts: RwLock::new(HashMap::new()), max_failures: max_failures.max(1), window, max_backoff }
+    }
+
+    // the counters a login for username from client is tracked under
+    pub fn keys(username: &str, client: Option<IpAddr>) -> Vec<String> {
+        let mut keys = vec![format!("{}{}", USER_PREFIX, username)];
+        keys.extend(client.map(|ip| format!("ip:{}", ip)));
+        keys
+    }
+
+    // reserves an attempt on every key before the password is checked, so concurrent logins can't
+    // all slip in under max_failures; Err is the seconds to wait. Every Ok must be followed by finish
+    pub async fn begin(&self, keys: &[String]) -> Result<(), u64> {
+        let now = now();
+        let mut attempts = self.attempts.write().await;
+        // a counter is forgotten once its window is over and its last lockout ended max_backoff ago,
+        // which is also what lets the backoff start from the beginning again
+        attempts.retain(|_, attempt| {
+            attempt.in_flight > 0 || now.saturating_sub(attempt.window_start) <= self.window || attempt.locked_until + self.max_backoff > now
+        });
+
+        let mut retry_after = None;
+        for key in keys {
+            let attempt = match attempts.get_mut(key) {
+                Some(attempt) => attempt,
+                None => continue,
+            };
+            if now.saturating_sub(attempt.window_start) > self.window {
+                attempt.failures = 0;
+                attempt.window_start = now;
+            }
+            let wait = if attempt.locked_until > now {
+                attempt.locked_until - now
+            } else if attempt.failures + attempt.in_flight >= self.max_failures {
+                IN_FLIGHT_RETRY
+            } else {
+                continue;
+            };
+            retry_after = retry_after.max(Some(wait));
+        }
+        if let Some(retry_after) = retry_after {
+            return Err(retry_after);
+        }
+
+        for key in keys {
+            let attempt = attempts.entry(key.clone()).or_insert_with(|| Attempts { window_start: now, ..Attempts::default() });
+            attempt.in_flight += 1;
+        }
+        Ok(())
+    }
+
+    // settles an attempt reserved by begin; a success only clears the username's counter, the
+    // client address keeps its failures and lockouts until they run out
+    pub async fn finish(&self, keys: &[String], authenticated: bool) {
+        let now = now();
+        let mut attempts = self.attempts.write().await;
+        for key in keys {
+            let attempt = attempts.entry(key.clone()).or_default();
+            attempt.in_flight = attempt.in_flight.saturating_sub(1);
+
+            if authenticated {
+                if key.starts_with(USER_PREFIX) {
+                    *attempt = Attempts { in_flight: attempt.in_flight, ..Attempts::default() };
+                }
+                continue;
+            }
+
+            if now.saturating_sub(attempt.window_start) > self.window {
+                attempt.failures = 0;
+                attempt.window_start = now;
+            }
+            attempt.failures += 1;
+            if attempt.failures >= self.max_failures {
+                let backoff = BASE_BACKOFF.saturating_mul(1u64 << attempt.lockouts.min(32)).min(self.max_backoff);
+                attempt.lockouts += 1;
+                attempt.locked_until = now + backoff;
+                attempt.failures = 0;
+                attempt.window_start = now;
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
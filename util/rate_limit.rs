@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use uuid::Uuid;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// a token bucket per user: a burst of up to per_minute requests, refilled at per_minute a minute,
+// so spreading requests over several addresses doesn't get a user past it
+pub struct UserRateLimiter {
+    buckets: Mutex<HashMap<Uuid, Bucket>>,
+    per_minute: u32,
+}
+
+impl UserRateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        UserRateLimiter { buckets: Mutex::new(HashMap::new()), per_minute: per_minute.max(1) }
+    }
+
+    // takes a token from the user's bucket; Err is the seconds until one is back
+    pub fn check(&self, user_id: Uuid) -> Result<(), u64> {
+        let now = Instant::now();
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let bucket = buckets.entry(user_id).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_second).ceil() as u64)
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use rand::rngs::OsRng;
+use rand::RngCore;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::hex;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+use crate::kv_silo::{encrypt_data, KVStore};
+
+const NONCE_LEN: usize = 16;
+// keeps the token signing key apart from anything else derived from the master key
+const SIGNING_CONTEXT: &[u8] = b"barn download token";
+
+#[derive(Serialize, Deserialize)]
+struct Grant {
+    key: String,
+    expires_at: u64,
+    uses_left: u32,
+}
+
+// pre-authenticated read grants for one secret each, persisted encrypted under the master key
+// like the users file; tokens are "<hex nonce>.<expires_at>.<hex HMAC>" so a forged or edited
+// one is turned away before the file is even read
+pub struct TokenStore {
+    path: PathBuf,
+    // the file is re-read on every call so grants issued by the CLI show up in a running server
+    lock: Mutex<()>,
+}
+
+impl TokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        TokenStore { path: path.into(), lock: Mutex::new(()) }
+    }
+
+    pub async fn issue(&self, key: &str, ttl_seconds: u64, max_uses: u32, master_key: &[u8]) -> io::Result<String> {
+        if max_uses == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "max_uses must be at least 1"));
+        }
+
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let expires_at = now() + ttl_seconds;
+        let tag = hmac::sign(&signing_key(master_key), &signed_bytes(key, expires_at, &nonce));
+
+        let _guard = self.lock.lock().await;
+        let mut grants = self.load(master_key).await?;
+        grants.insert(hex::encode(nonce), Grant { key: key.to_string(), expires_at, uses_left: max_uses });
+        self.save(&grants, master_key).await?;
+
+        Ok(format!("{}.{}.{}", hex::encode(nonce), expires_at, hex::encode(tag.as_ref())))
+    }
+
+    // true when token would grant a read of key, without using up one of its uses; a handler
+    // checks this first and only redeems once the read has succeeded
+    pub async fn is_valid(&self, key: &str, token: &str, master_key: &[u8]) -> io::Result<bool> {
+        let nonce = match verify_signature(key, token, master_key) {
+            Some(nonce) => nonce,
+            None => return Ok(false),
+        };
+
+        let _guard = self.lock.lock().await;
+        let grants = self.load(master_key).await?;
+        Ok(grants.get(nonce).is_some_and(|grant| grant.key == key && grant.expires_at > now()))
+    }
+
+    // true when token grants a read of key, using up one of its uses
+    pub async fn redeem(&self, key: &str, token: &str, master_key: &[u8]) -> io::Result<bool> {
+        let nonce = match verify_signature(key, token, master_key) {
+            Some(nonce) => nonce,
+            None => return Ok(false),
+        };
+
+        let _guard = self.lock.lock().await;
+        let mut grants = self.load(master_key).await?;
+        let now = now();
+        let before = grants.len();
+        grants.retain(|_, grant| grant.expires_at > now);
+
+        let granted = match grants.get_mut(nonce) {
+            Some(grant) if grant.key == key => {
+                grant.uses_left -= 1;
+                if grant.uses_left == 0 {
+                    grants.remove(nonce);
+                }
+                true
+            }
+            _ => false,
+        };
+        if granted || grants.len() != before {
+            self.save(&grants, master_key).await?;
+        }
+        Ok(granted)
+    }
+
+    // re-encrypts the file under new_key; outstanding tokens are signed with a key derived from
+    // old_key and can never verify again, so they are dropped, and how many is returned
+    pub async fn rotate_master_key(&self, old_key: &[u8], new_key: &[u8]) -> io::Result<usize> {
+        let _guard = self.lock.lock().await;
+        let revoked = self.load(old_key).await?.len();
+        self.save(&HashMap::new(), new_key).await?;
+        Ok(revoked)
+    }
+
+    async fn load(&self, master_key: &[u8]) -> io::Result<HashMap<String, Grant>> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        let plaintext = KVStore::new().decrypt_file(self.path.to_str().unwrap_or_default(), master_key).await?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    async fn save(&self, grants: &HashMap<String, Grant>, master_key: &[u8]) -> io::Result<()> {
+        let (iv, encrypted_data) = encrypt_data(master_key, &serde_json::to_vec(grants)?)?;
+        KVStore::new()
+            .save_to_file_encrypted(self.path.to_str().unwrap_or_default(), &encrypted_data, &iv)
+            .await
+    }
+}
+
+// the token's hex nonce when it is well formed and its HMAC checks out for key
+fn verify_signature<'a>(key: &str, token: &'a str, master_key: &[u8]) -> Option<&'a str> {
+    let mut parts = token.split('.');
+    let (nonce, expires_at, tag) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(nonce), Some(expires_at), Some(tag), None) => (nonce, expires_at, tag),
+        _ => return None,
+    };
+    let (nonce_bytes, expires_at, tag) = match (hex::decode(nonce), expires_at.parse::<u64>(), hex::decode(tag)) {
+        (Ok(nonce_bytes), Ok(expires_at), Ok(tag)) => (nonce_bytes, expires_at, tag),
+        _ => return None,
+    };
+    hmac::verify(&signing_key(master_key), &signed_bytes(key, expires_at, &nonce_bytes), &tag).ok()?;
+    Some(nonce)
+}
+
+fn signing_key(master_key: &[u8]) -> hmac::Key {
+    let derived = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, master_key), SIGNING_CONTEXT);
+    hmac::Key::new(hmac::HMAC_SHA256, derived.as_ref())
+}
+
+// key || expires_at || nonce; the key is length-prefixed so no two inputs run together
+fn signed_bytes(key: &str, expires_at: u64, nonce: &[u8]) -> Vec<u8> {
+    let mut bytes = (key.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(key.as_bytes());
+    bytes.extend_from_slice(&expires_at.to_be_bytes());
+    bytes.extend_from_slice(nonce);
+    bytes
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
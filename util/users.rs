@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::kv_silo::{encrypt_data, KVStore};
+
+// auto-tuning never picks a cost below this, however slow the machine
+const MIN_TUNED_COST: u32 = 10;
+// bcrypt's own upper limit, which the crate doesn't export
+const MAX_COST: u32 = 31;
+
+// username -> bcrypt hash, persisted encrypted under the master key like the secrets file
+pub struct UserStore {
+    users: RwLock<HashMap<String, String>>,
+    path: PathBuf,
+}
+
+impl UserStore {
+    // a missing file just means nobody has registered yet
+    pub async fn load(path: impl Into<PathBuf>, master_key: &[u8]) -> io::Result<Self> {
+        let path = path.into();
+        let users = if path.exists() {
+            let plaintext = KVStore::new()
+                .decrypt_file(path.to_str().unwrap_or_default(), master_key)
+                .await?;
+            serde_json::from_slice(&plaintext)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(UserStore {
+            users: RwLock::new(users),
+            path,
+        })
+    }
+
+    pub async fn save(&self, master_key: &[u8]) -> io::Result<()> {
+        let users = self.users.read().await;
+        let (iv, encrypted_data) = encrypt_data(master_key, &serde_json::to_vec(&*users)?)?;
+        KVStore::new()
+            .save_to_file_encrypted(self.path.to_str().unwrap_or_default(), &encrypted_data, &iv)
+            .await
+    }
+
+    pub async fn register(&self, username: &str, password_hash: String) -> io::Result<()> {
+        let mut users = self.users.write().await;
+        if users.contains_key(username) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("User {} already exists", username)));
+        }
+        users.insert(username.to_string(), password_hash);
+        Ok(())
+    }
+
+    pub async fn get(&self, username: &str) -> Option<String> {
+        let users = self.users.read().await;
+        users.get(username).cloned()
+    }
+
+    pub async fn exists(&self, username: &str) -> bool {
+        let users = self.users.read().await;
+        users.contains_key(username)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.users.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.users.read().await.is_empty()
+    }
+}
+
+// false for unknown users as well as wrong passwords, so callers can't tell the two apart
+pub async fn authenticate_user(store: &UserStore, username: &str, password: &str) -> bool {
+    match store.get(username).await {
+        Some(password_hash) => bcrypt::verify(password, &password_hash).unwrap_or(false),
+        None => false,
+    }
+}
+
+// the lowest cost whose hash takes at least target on this machine, or bcrypt's highest cost
+pub fn tune_bcrypt_cost(target: Duration) -> io::Result<u32> {
+    for cost in MIN_TUNED_COST..MAX_COST {
+        let started = Instant::now();
+        bcrypt::hash("barn cost probe", cost).map_err(io::Error::other)?;
+        if started.elapsed() >= target {
+            return Ok(cost);
+        }
+    }
+    Ok(MAX_COST)
+}
+
+// the cost saved by `serve --auto-tune-bcrypt-cost`, or bcrypt's default when it never ran
+pub fn load_bcrypt_cost(path: impl AsRef<Path>) -> io::Result<u32> {
+    match std::fs::read_to_string(path) {
+        Ok(cost) => cost
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid bcrypt cost: {}", e))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(bcrypt::DEFAULT_COST),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn save_bcrypt_cost(path: impl AsRef<Path>, cost: u32) -> io::Result<()> {
+    std::fs::write(path, format!("{}\n", cost))
+}
+
+// hashes the password and records the user, refusing usernames that are already taken
+pub async fn register_user(store: &UserStore, username: &str, password: &str, cost: u32, master_key: &[u8]) -> io::Result<()> {
+    if username.is_empty() || password.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Username and password must not be empty"));
+    }
+
+    let password_hash = bcrypt::hash(password, cost).map_err(io::Error::other)?;
+    store.register(username, password_hash).await?;
+    store.save(master_key).await
+}
@@ -0,0 +1,65 @@
+use serde_json::Value;
+use std::fmt;
+use zeroize::Zeroizing;
+
+// which KV secrets engine a `vault kv get -format=json` dump came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvVersion {
+    V1,
+    V2,
+}
+
+#[derive(Debug)]
+pub enum VaultImportError {
+    InvalidJson(String),
+    // the dump has no `data` object, or a KV v2 dump has no `data.data`
+    MissingData,
+}
+
+impl fmt::Display for VaultImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VaultImportError::InvalidJson(msg) => write!(f, "not valid JSON: {}", msg),
+            VaultImportError::MissingData => write!(f, "no data object in the Vault output"),
+        }
+    }
+}
+
+impl std::error::Error for VaultImportError {}
+
+pub struct VaultSecret {
+    pub version: KvVersion,
+    // `{path}/{field}` and the field's value; strings are taken as they are, anything else as JSON
+    pub fields: Vec<(String, Zeroizing<Vec<u8>>)>,
+    // seconds, when Vault gave a lease; only an approximate TTL, since it counts from the read
+    pub lease_duration: Option<u64>,
+}
+
+// KV v2 nests the fields under data.data next to data.metadata; KV v1 puts them straight in data
+pub fn parse_kv_get(json: &[u8], path: &str) -> Result<VaultSecret, VaultImportError> {
+    let output: Value = serde_json::from_slice(json).map_err(|e| VaultImportError::InvalidJson(e.to_string()))?;
+    let data = output.get("data").and_then(Value::as_object).ok_or(VaultImportError::MissingData)?;
+
+    let (version, fields) = if data.get("metadata").is_some_and(Value::is_object) {
+        let fields = data.get("data").and_then(Value::as_object).ok_or(VaultImportError::MissingData)?;
+        (KvVersion::V2, fields)
+    } else {
+        (KvVersion::V1, data)
+    };
+
+    let path = path.trim_end_matches('/');
+    let mut imported: Vec<(String, Zeroizing<Vec<u8>>)> = fields
+        .iter()
+        .map(|(field, value)| {
+            let value = match value {
+                Value::String(value) => value.clone().into_bytes(),
+                value => value.to_string().into_bytes(),
+            };
+            (format!("{}/{}", path, field), Zeroizing::new(value))
+        })
+        .collect();
+    imported.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let lease_duration = output.get("lease_duration").and_then(Value::as_u64).filter(|&seconds| seconds > 0);
+    Ok(VaultSecret { version, fields: imported, lease_duration })
+}